@@ -1,15 +1,52 @@
+use std::{
+    fs,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
 use async_graphql::{Context, Error, Result};
-use axum::http::HeaderMap;
+use axum::http::{header::AUTHORIZATION, HeaderMap};
 use bson::Uuid;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use log::{info, warn};
 use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    settings::{AuthenticationMode, AuthenticationSettings},
+    wishlist::{Wishlist, WishlistVisibility},
+};
+
+/// Error message of a missing or malformed Authorized-User header.
+const MISSING_HEADER_MESSAGE: &str =
+    "Authentication failed. Authorized-User header is not set or could not be parsed.";
+/// Interval in which the keys of a JWKS URL are refetched, so that rotated keys are picked up.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(900);
 
 /// Authorized-User HTTP header.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct AuthorizedUserHeader {
     id: Uuid,
     roles: Vec<Role>,
 }
 
+/// Reason why the identity of the user of a request could not be retrieved.
+///
+/// Written in the context data instead of the AuthorizedUserHeader and reported by operations that require a user.
+pub struct AuthenticationFailure(pub Error);
+
+impl AuthorizedUserHeader {
+    /// UUID of the authorized user.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
 /// Extraction of AuthorizedUserHeader from HeaderMap.
 impl TryFrom<&HeaderMap> for AuthorizedUserHeader {
     type Error = Error;
@@ -25,10 +62,276 @@ impl TryFrom<&HeaderMap> for AuthorizedUserHeader {
                 return Ok(authenticate_user_header);
             }
         }
-        Err(Error::new("Authentication failed. Authorized-User header is not set or could not be parsed."))
+        Err(Error::new(MISSING_HEADER_MESSAGE))
+    }
+}
+
+/// Verification of the identity of the user of a request.
+#[derive(Clone)]
+pub enum Authenticator {
+    /// Trusts the Authorized-User header, which is set by a gateway that verified the user.
+    Header,
+    /// Verifies a signed JWT of the `Authorization: Bearer` header, the Authorized-User header is ignored.
+    Jwt(Arc<JwtVerifier>),
+}
+
+impl Authenticator {
+    /// Builds the authenticator of the configured mode, JWKS are read from their file or fetched from their URL.
+    ///
+    /// Returns the reason if the verification keys can not be loaded.
+    pub async fn from_settings(settings: &AuthenticationSettings) -> Result<Self, String> {
+        match settings.mode {
+            AuthenticationMode::Header => Ok(Self::Header),
+            AuthenticationMode::Jwt => {
+                let keys = load_keys(settings).await?;
+                if keys.is_empty() {
+                    return Err(
+                        "JWKS does not contain a key usable for JWT signatures.".to_string()
+                    );
+                }
+                let mut validation = Validation::default();
+                validation.set_required_spec_claims(&["exp", "aud", "iss"]);
+                validation.set_audience(&[settings.audience.clone().unwrap_or_default()]);
+                validation.set_issuer(&[settings.issuer.clone().unwrap_or_default()]);
+                validation.leeway = settings.leeway_seconds;
+                Ok(Self::Jwt(Arc::new(JwtVerifier {
+                    keys: RwLock::new(keys),
+                    jwks_url: settings.jwks_url.clone(),
+                    validation,
+                    user_id_claim: settings.user_id_claim.clone(),
+                    roles_claim: settings.roles_claim.clone(),
+                })))
+            }
+        }
+    }
+
+    /// Retrieves the verified identity of the user of a request from its headers.
+    ///
+    /// Returns a GraphQL Error if the identity is missing or can not be verified.
+    pub fn authorized_user(&self, header_map: &HeaderMap) -> Result<AuthorizedUserHeader> {
+        match self {
+            Self::Header => AuthorizedUserHeader::try_from(header_map)
+                .map_err(|_| Error::new(MISSING_HEADER_MESSAGE)),
+            Self::Jwt(verifier) => {
+                let token = header_map
+                    .get(AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .ok_or_else(|| {
+                        Error::new(
+                            "Authentication failed. Authorization header does not contain a bearer token.",
+                        )
+                    })?;
+                verifier.verify(token.trim())
+            }
+        }
+    }
+
+    /// Periodically refetches the keys of the JWKS URL, returns immediately if keys are not fetched from a URL.
+    ///
+    /// Keys are kept if refetching fails.
+    pub async fn refresh_keys(self) {
+        let Self::Jwt(verifier) = self else {
+            return;
+        };
+        let Some(definitely_jwks_url) = verifier.jwks_url.clone() else {
+            return;
+        };
+        loop {
+            tokio::time::sleep(JWKS_REFRESH_INTERVAL).await;
+            match fetch_jwks(&definitely_jwks_url)
+                .await
+                .map(|jwks| jwks_keys(&jwks))
+            {
+                Ok(keys) if !keys.is_empty() => {
+                    info!("Refetched {} JWT verification keys.", keys.len());
+                    *verifier.keys.write().unwrap() = keys;
+                }
+                Ok(_) => warn!(
+                    "Refetched JWKS does not contain a usable key, keeping the previous keys."
+                ),
+                Err(error) => warn!(
+                    "Refetching JWKS failed, keeping the previous keys: {}",
+                    error
+                ),
+            }
+        }
+    }
+}
+
+/// Verifier of signed JWTs.
+pub struct JwtVerifier {
+    /// Keys JWT signatures are verified with.
+    keys: RwLock<Vec<VerificationKey>>,
+    /// URL the keys are refetched from, `None` if the keys are not fetched.
+    jwks_url: Option<String>,
+    /// Validation of `exp`, `aud` and `iss`, the algorithms are restricted per key.
+    validation: Validation,
+    /// Claim that holds the UUID of the user.
+    user_id_claim: String,
+    /// Dot separated path of the claim that holds the roles of the user.
+    roles_claim: String,
+}
+
+/// Key JWT signatures are verified with.
+struct VerificationKey {
+    /// Key id, which selects the key through the `kid` of the JWT header, matches any JWT if `None`.
+    kid: Option<String>,
+    key: DecodingKey,
+    /// Algorithms the key accepts, all of the same family, as a JWT must not choose the family of the key.
+    algorithms: Vec<Algorithm>,
+}
+
+impl JwtVerifier {
+    /// Verifies the signature and the claims of a JWT and maps its claims to the identity of the user.
+    ///
+    /// Roles that are not known to the service are ignored.
+    fn verify(&self, token: &str) -> Result<AuthorizedUserHeader> {
+        let header = decode_header(token).map_err(authentication_error)?;
+        let keys = self.keys.read().unwrap();
+        let mut candidates = keys
+            .iter()
+            .filter(|key| key.kid.is_none() || header.kid.is_none() || key.kid == header.kid)
+            .filter(|key| key.algorithms.contains(&header.alg))
+            .peekable();
+        if candidates.peek().is_none() {
+            let message = format!(
+                "Authentication failed. No key accepts JWT of algorithm: `{:?}` and key id: `{}`.",
+                header.alg,
+                header.kid.unwrap_or_default()
+            );
+            return Err(Error::new(message));
+        }
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+        let mut last_error = None;
+        for candidate in candidates {
+            match decode::<Value>(token, &candidate.key, &validation) {
+                Ok(token_data) => return self.authorized_user(&token_data.claims),
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(authentication_error(last_error.unwrap()))
+    }
+
+    /// Maps the claims of a verified JWT to the identity of the user.
+    fn authorized_user(&self, claims: &Value) -> Result<AuthorizedUserHeader> {
+        let id = claims
+            .get(&self.user_id_claim)
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| {
+                let message = format!(
+                    "Authentication failed. JWT claim: `{}` is not a UUID.",
+                    self.user_id_claim
+                );
+                Error::new(message)
+            })?;
+        let roles = self
+            .roles_claim
+            .split('.')
+            .try_fold(claims, |claim, segment| claim.get(segment))
+            .and_then(Value::as_array)
+            .map(|roles| {
+                roles
+                    .iter()
+                    .filter_map(|role| serde_json::from_value(role.clone()).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(AuthorizedUserHeader { id, roles })
     }
 }
 
+/// Loads the verification keys of the configured key source.
+async fn load_keys(settings: &AuthenticationSettings) -> Result<Vec<VerificationKey>, String> {
+    if let Some(definitely_hmac_secret) = &settings.hmac_secret {
+        return Ok(vec![VerificationKey {
+            kid: None,
+            key: DecodingKey::from_secret(definitely_hmac_secret.as_bytes()),
+            algorithms: vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512],
+        }]);
+    }
+    let jwks = match (&settings.jwks_path, &settings.jwks_url) {
+        (Some(path), _) => {
+            let content = fs::read_to_string(path)
+                .map_err(|error| format!("Reading JWKS: `{}` failed: {}", path, error))?;
+            serde_json::from_str(&content)
+                .map_err(|error| format!("Parsing JWKS: `{}` failed: {}", path, error))?
+        }
+        (None, Some(url)) => fetch_jwks(url).await?,
+        (None, None) => return Err("No JWT verification key is configured.".to_string()),
+    };
+    Ok(jwks_keys(&jwks))
+}
+
+/// Fetches a JSON Web Key Set from a URL.
+async fn fetch_jwks(url: &str) -> Result<JwkSet, String> {
+    let fetch = async {
+        reqwest::get(url)
+            .await?
+            .error_for_status()?
+            .json::<JwkSet>()
+            .await
+    };
+    fetch
+        .await
+        .map_err(|error| format!("Fetching JWKS: `{}` failed: {}", url, error))
+}
+
+/// Verification keys of the signature keys of a JSON Web Key Set, encryption keys and unsupported keys are skipped.
+fn jwks_keys(jwks: &JwkSet) -> Vec<VerificationKey> {
+    jwks.keys
+        .iter()
+        .filter(|jwk| jwk.common.public_key_use != Some(PublicKeyUse::Encryption))
+        .filter_map(|jwk| {
+            let algorithms = jwk_algorithms(jwk);
+            let key = DecodingKey::from_jwk(jwk).ok()?;
+            (!algorithms.is_empty()).then(|| VerificationKey {
+                kid: jwk.common.key_id.clone(),
+                key,
+                algorithms,
+            })
+        })
+        .collect()
+}
+
+/// Algorithms a JSON Web Key accepts, the `alg` of the key if set, otherwise all algorithms of its key type.
+fn jwk_algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(key_algorithm) = jwk.common.key_algorithm {
+        return Algorithm::from_str(&key_algorithm.to_string())
+            .into_iter()
+            .collect();
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(parameters) => match parameters.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => Vec::new(),
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => {
+            vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+        }
+    }
+}
+
+/// GraphQL Error of a JWT that could not be verified.
+fn authentication_error(error: jsonwebtoken::errors::Error) -> Error {
+    Error::new(format!(
+        "Authentication failed. JWT is not valid: {}.",
+        error
+    ))
+}
+
 /// Role of user.
 #[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -49,34 +352,222 @@ impl Role {
     }
 }
 
+/// Operation on a wishlist, which requires a permission.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WishlistPermission {
+    /// Reading the wishlist.
+    Read,
+    /// Modifying name and items of the wishlist.
+    Write,
+    /// Operations reserved to the owner, like deleting, sharing or managing members.
+    Owner,
+}
+
 /// Authenticate user of UUID for a Context.
 pub fn authenticate_user(ctx: &Context, id: Uuid) -> Result<()> {
-    match ctx.data::<AuthorizedUserHeader>() {
-        Ok(authenticate_user_header) => check_permissions(&authenticate_user_header, id),
-        Err(_) => Err(Error::new("Authentication failed. Authorized-User header is not set or could not be parsed.")),
+    let authenticate_user_header = authorized_user_header(ctx)?;
+    check_permissions(authenticate_user_header, id)
+}
+
+/// Retrieves the UUID of the user of the AuthorizedUserHeader of a Context.
+pub fn authenticated_user_id(ctx: &Context) -> Result<Uuid> {
+    authorized_user_header(ctx).map(|header| header.id)
+}
+
+/// Authorize an operation on a wishlist for a Context.
+///
+/// The owner and users with a permissive role are permitted all operations.
+/// Accepted members are permitted the operations of their role, public wishlists can be read by anyone.
+/// Link-shared wishlists are additionally readable through their share token, which is checked by the share query itself.
+///
+/// * `wishlist` - Wishlist to operate on.
+/// * `permission` - Permission the operation requires.
+pub fn authorize_wishlist(
+    ctx: &Context,
+    wishlist: &Wishlist,
+    permission: WishlistPermission,
+) -> Result<()> {
+    if permission == WishlistPermission::Read && wishlist.visibility == WishlistVisibility::Public {
+        return Ok(());
     }
+    let authenticate_user_header = authorized_user_header(ctx)?;
+    authorize_wishlist_of_user(Some(authenticate_user_header), wishlist, permission)
+}
+
+/// Authorize an operation on a wishlist for the AuthorizedUserHeader of a reader, `None` for anonymous readers.
+///
+/// Applies the rules of `authorize_wishlist` where the Context is not available, like in subscription streams.
+///
+/// * `authenticate_user_header` - AuthorizedUserHeader of the reader.
+/// * `wishlist` - Wishlist to operate on.
+/// * `permission` - Permission the operation requires.
+pub fn authorize_wishlist_of_user(
+    authenticate_user_header: Option<&AuthorizedUserHeader>,
+    wishlist: &Wishlist,
+    permission: WishlistPermission,
+) -> Result<()> {
+    if permission == WishlistPermission::Read && wishlist.visibility == WishlistVisibility::Public {
+        return Ok(());
+    }
+    let authenticate_user_header =
+        authenticate_user_header.ok_or_else(|| Error::new(MISSING_HEADER_MESSAGE))?;
+    if check_permissions(authenticate_user_header, wishlist.user._id).is_ok() {
+        return Ok(());
+    }
+    let is_member_permitted = wishlist
+        .accepted_member(authenticate_user_header.id)
+        .is_some_and(|member| member.role.permits(permission));
+    match is_member_permitted {
+        true => Ok(()),
+        false => {
+            let message = format!(
+                "Authentication failed for user of UUID: `{}`. Operation on wishlist of id: `{}` not permitted.",
+                authenticate_user_header.id, wishlist._id
+            );
+            Err(Error::new(message))
+        }
+    }
+}
+
+/// Authenticate user with a permissive role for a Context.
+///
+/// Used for administrative operations that are not bound to a specific user.
+pub fn authenticate_permissive_user(ctx: &Context) -> Result<()> {
+    let authenticate_user_header = authorized_user_header(ctx)?;
+    if authenticate_user_header
+        .roles
+        .iter()
+        .any(|r| r.is_permissive())
+    {
+        Ok(())
+    } else {
+        let message = format!(
+            "Authentication failed for user of UUID: `{}`. Operation requires a permissive role.",
+            authenticate_user_header.id
+        );
+        Err(Error::new(message))
+    }
+}
+
+/// Retrieves the AuthorizedUserHeader of a Context.
+///
+/// Returns the AuthenticationFailure of the Context if the identity of the user could not be retrieved.
+fn authorized_user_header<'a>(ctx: &Context<'a>) -> Result<&'a AuthorizedUserHeader> {
+    ctx.data::<AuthorizedUserHeader>()
+        .map_err(|_| match ctx.data_opt::<AuthenticationFailure>() {
+            Some(AuthenticationFailure(error)) => error.clone(),
+            None => Error::new(MISSING_HEADER_MESSAGE),
+        })
 }
 
 /// Check if user of UUID has a valid permission according to the AuthorizedUserHeader.
 ///
 /// Permission is valid if the user has `Role::Buyer` and the same UUID as provided in the function parameter.
 /// Permission is valid if the user has a permissive role: `user.is_permissive() == true`, regardless of the users UUID.
-pub fn check_permissions(
-    authenticate_user_header: &AuthorizedUserHeader,
-    id: Uuid,
-) -> Result<()> {
+pub fn check_permissions(authenticate_user_header: &AuthorizedUserHeader, id: Uuid) -> Result<()> {
     if authenticate_user_header
         .roles
         .iter()
         .any(|r| r.is_permissive())
         || authenticate_user_header.id == id
     {
-        return Ok(());
+        Ok(())
     } else {
         let message = format!(
             "Authentication failed for user of UUID: `{}`. Operation not permitted.",
             authenticate_user_header.id
         );
-        return Err(Error::new(message));
+        Err(Error::new(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::integration_tests::{jwt_claims, jwt_settings};
+
+    /// JWT of claims signed with HS256 and the key id in the header.
+    fn sign_jwt_with_kid(claims: &Value, secret: &str, kid: &str) -> String {
+        let header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::default()
+        };
+        encode(
+            &header,
+            claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    /// Authenticator of a JWKS file with a key per key id and secret.
+    async fn jwks_file_authenticator(keys: &[(&str, &str)]) -> Authenticator {
+        let jwks = json!({
+            "keys": keys
+                .iter()
+                .map(|(kid, secret)| json!({
+                    "kty": "oct",
+                    "kid": kid,
+                    "alg": "HS256",
+                    "k": base64::Engine::encode(
+                        &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                        secret,
+                    ),
+                }))
+                .collect::<Vec<Value>>(),
+        });
+        let path = std::env::temp_dir().join(format!("jwks-{}.json", Uuid::new()));
+        fs::write(&path, jwks.to_string()).unwrap();
+        let mut settings = jwt_settings().authentication;
+        settings.hmac_secret = None;
+        settings.jwks_path = Some(path.display().to_string());
+        let authenticator = Authenticator::from_settings(&settings).await.unwrap();
+        fs::remove_file(path).unwrap();
+        authenticator
+    }
+
+    /// Headers with a JWT in the `Authorization: Bearer` header.
+    fn bearer_headers(token: &str) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        let value = format!("Bearer {}", token).parse().unwrap();
+        header_map.insert(AUTHORIZATION, value);
+        header_map
+    }
+
+    #[tokio::test]
+    async fn verifies_token_with_jwks_key_of_its_key_id() {
+        let authenticator =
+            jwks_file_authenticator(&[("old", "old-secret"), ("current", "current-secret")]).await;
+        let id = Uuid::new();
+        let token = sign_jwt_with_kid(
+            &jwt_claims(id, &["employee", "unknown"]),
+            "current-secret",
+            "current",
+        );
+
+        let authorized_user = authenticator
+            .authorized_user(&bearer_headers(&token))
+            .unwrap();
+
+        assert_eq!(authorized_user.id(), id);
+        assert_eq!(authorized_user.roles, vec![Role::Employee]);
+    }
+
+    #[tokio::test]
+    async fn rejects_token_of_unknown_key_id() {
+        let authenticator = jwks_file_authenticator(&[("current", "current-secret")]).await;
+        let token = sign_jwt_with_kid(&jwt_claims(Uuid::new(), &[]), "current-secret", "rotated");
+
+        let error = authenticator
+            .authorized_user(&bearer_headers(&token))
+            .unwrap_err();
+
+        assert_eq!(
+            error.message,
+            "Authentication failed. No key accepts JWT of algorithm: `HS256` and key id: `rotated`."
+        );
     }
 }
@@ -1,4 +1,13 @@
-use async_graphql::{OutputType, SimpleObject};
+use async_graphql::{Error, OutputType, Result, SimpleObject};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bson::{Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb_cursor_pagination::{CursorDirections, FindResult, PaginatedCursor};
+
+/// Page size of the MongoDB pagination if neither `first` nor `last` is set.
+///
+/// Equals the default of the MongoDB pagination library, queries without `first` or `last` were always capped to it.
+pub const DEFAULT_PAGE_SIZE: u32 = 25;
 
 /// A base connection for an OutputType.
 #[derive(SimpleObject)]
@@ -10,28 +19,239 @@ pub struct BaseConnection<T: OutputType> {
     pub has_next_page: bool,
     /// The total amount of items in this connection.
     pub total_count: u64,
+    /// Cursor based pagination information of this connection.
+    pub page_info: PageInfo,
 }
 
-use mongodb_cursor_pagination::FindResult;
+/// Relay-style pagination information of a connection.
+#[derive(SimpleObject, Default, Clone)]
+#[graphql(shareable)]
+pub struct PageInfo {
+    /// Whether this connection has a next page.
+    pub has_next_page: bool,
+    /// Whether this connection has a previous page.
+    pub has_previous_page: bool,
+    /// Cursor of the first item of this page, used as `before` to retrieve the previous page.
+    pub start_cursor: Option<String>,
+    /// Cursor of the last item of this page, used as `after` to retrieve the next page.
+    pub end_cursor: Option<String>,
+}
 
 pub struct FindResultWrapper<Node>(pub FindResult<Node>);
 
-/// Object that writes total count of items in a query, regardless of pagination.
-#[derive(SimpleObject)]
-pub struct AdditionalFields {
-    total_count: u64,
-}
-
 /// Implementation of conversion from MongoDB pagination to GraphQL Connection.
 impl<Node> From<FindResultWrapper<Node>> for BaseConnection<Node>
 where
     Node: OutputType,
 {
     fn from(value: FindResultWrapper<Node>) -> Self {
+        let page_info = PageInfo {
+            has_next_page: value.0.page_info.has_next_page,
+            has_previous_page: value.0.page_info.has_previous_page,
+            start_cursor: value.0.page_info.start_cursor,
+            end_cursor: value.0.page_info.next_cursor,
+        };
         BaseConnection {
             nodes: value.0.items,
-            has_next_page: value.0.page_info.has_next_page,
+            has_next_page: page_info.has_next_page,
             total_count: value.0.total_count,
+            page_info,
+        }
+    }
+}
+
+impl<Node> FindResultWrapper<Node> {
+    /// Reverses the items and the page information of a result that was retrieved in reversed sort order.
+    pub fn reversed(mut self) -> Self {
+        let page_info = &mut self.0.page_info;
+        std::mem::swap(
+            &mut page_info.has_next_page,
+            &mut page_info.has_previous_page,
+        );
+        std::mem::swap(&mut page_info.start_cursor, &mut page_info.next_cursor);
+        self.0.items.reverse();
+        self.0.edges.reverse();
+        self
+    }
+
+    /// Limits the result of a query with skip to the page size.
+    ///
+    /// On the last page of a query with skip, the MongoDB pagination keeps the document it retrieved beyond the limit
+    /// to find out if there is a next page.
+    pub fn limited(mut self, page_size: usize, skip: u64) -> Self {
+        self.0.items.truncate(page_size);
+        self.0.edges.truncate(page_size);
+        let page_info = &mut self.0.page_info;
+        page_info.has_next_page = skip.saturating_add(page_size as u64) < self.0.total_count;
+        page_info.next_cursor = self.0.edges.last().map(|edge| edge.cursor.clone());
+        self
+    }
+}
+
+/// Relay-style cursor pagination arguments of a connection.
+pub struct CursorArguments {
+    /// Retrieve the `first` N items.
+    pub first: Option<u32>,
+    /// Retrieve items after this cursor.
+    pub after: Option<String>,
+    /// Retrieve the `last` N items.
+    pub last: Option<u32>,
+    /// Retrieve items before this cursor.
+    pub before: Option<String>,
+}
+
+/// Paginated MongoDB cursor built from `CursorArguments`.
+pub struct CursorPagination {
+    /// Cursor to execute the query with.
+    pub paginated_cursor: PaginatedCursor,
+    /// Whether the query runs in reversed sort order, its result needs to be reversed with `FindResultWrapper::reversed`.
+    pub reversed: bool,
+    /// Maximum amount of items of the page.
+    pub page_size: usize,
+    /// Amount of skipped items, `None` if the query has a cursor or skips nothing.
+    ///
+    /// The result of a query with skip needs to be limited with `FindResultWrapper::limited`.
+    pub skip: Option<u64>,
+}
+
+impl CursorArguments {
+    /// Builds a paginated MongoDB cursor for these arguments.
+    ///
+    /// * `find_options` - Find options including sorting and an optional skip. The sort document needs to contain `_id` as tiebreaker.
+    pub fn into_pagination(self, mut find_options: FindOptions) -> Result<CursorPagination> {
+        self.validate()?;
+        let page_size = self.first.or(self.last).unwrap_or(DEFAULT_PAGE_SIZE);
+        find_options.limit = Some(i64::from(page_size));
+        let (cursor, direction, reversed) = match (self.after, self.before, self.last) {
+            (_, Some(before), _) => (Some(before), Some(CursorDirections::Previous), false),
+            (Some(after), None, _) => (Some(after), Some(CursorDirections::Next), false),
+            (None, None, Some(_)) => {
+                find_options.sort = find_options.sort.map(reverse_sort);
+                (None, None, true)
+            }
+            (None, None, None) => (None, None, false),
+        };
+        let skip = match cursor {
+            Some(_) => None,
+            None => find_options.skip.filter(|skip| *skip > 0),
+        };
+        Ok(CursorPagination {
+            paginated_cursor: PaginatedCursor::new(Some(find_options), cursor, direction),
+            reversed,
+            page_size: page_size as usize,
+            skip,
+        })
+    }
+
+    /// Checks that the arguments can be combined and that the cursors are valid.
+    pub fn validate(&self) -> Result<()> {
+        if self.first.is_some() && self.last.is_some() {
+            return Err(Error::new(
+                "Pagination with both `first` and `last` is not supported.",
+            ));
+        }
+        if self.after.is_some() && self.before.is_some() {
+            return Err(Error::new(
+                "Pagination with both `after` and `before` is not supported.",
+            ));
         }
+        for cursor in self.after.iter().chain(self.before.iter()) {
+            validate_cursor(cursor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a cursor is base64 encoded BSON containing the `_id` tiebreaker, as produced by the MongoDB pagination.
+fn validate_cursor(cursor: &str) -> Result<()> {
+    let is_valid = STANDARD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| Document::from_reader(bytes.as_slice()).ok())
+        .is_some_and(|document| document.contains_key("_id"));
+    match is_valid {
+        true => Ok(()),
+        false => {
+            let message = format!("Cursor: `{}` is not valid.", cursor);
+            Err(Error::new(message))
+        }
+    }
+}
+
+/// Inverts all directions of a MongoDB sort document.
+fn reverse_sort(sort: Document) -> Document {
+    sort.into_iter()
+        .map(|(key, value)| match value {
+            Bson::Int32(direction) => (key, Bson::Int32(-direction)),
+            Bson::Int64(direction) => (key, Bson::Int64(-direction)),
+            _ => (key, value),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use mongodb_cursor_pagination::{Edge, PageInfo as CursorPageInfo};
+
+    use super::*;
+    use crate::order_datatypes::{OrderDirection, WishlistOrderField, WishlistOrderInput};
+
+    /// Result of the MongoDB pagination with an edge per item.
+    fn find_result(items: Vec<u32>, total_count: u64, has_next_page: bool) -> FindResult<u32> {
+        FindResult {
+            page_info: CursorPageInfo {
+                has_next_page,
+                has_previous_page: false,
+                start_cursor: None,
+                next_cursor: None,
+            },
+            edges: items
+                .iter()
+                .map(|item| Edge {
+                    cursor: item.to_string(),
+                })
+                .collect(),
+            total_count,
+            items,
+        }
+    }
+
+    #[test]
+    fn sorts_by_field_with_id_tiebreaker_and_reverses_for_last() {
+        let order = WishlistOrderInput {
+            direction: Some(OrderDirection::Desc),
+            field: Some(WishlistOrderField::Name),
+        };
+
+        let sort = order.sort_doc();
+
+        assert_eq!(sort, doc! {"name": -1, "_id": -1});
+        assert_eq!(reverse_sort(sort), doc! {"name": 1, "_id": 1});
+    }
+
+    #[test]
+    fn limits_page_of_query_with_skip() {
+        // The MongoDB pagination keeps the item beyond the limit if it is the last item.
+        let find_result_wrapper = FindResultWrapper(find_result(vec![2, 3], 3, false));
+
+        let limited = find_result_wrapper.limited(1, 1);
+
+        assert_eq!(limited.0.items, vec![2]);
+        assert!(limited.0.page_info.has_next_page);
+        assert_eq!(limited.0.page_info.next_cursor.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn reverses_result_of_reversed_query() {
+        let mut result = find_result(vec![3, 2], 3, true);
+        result.page_info.next_cursor = Some("2".to_string());
+
+        let reversed = FindResultWrapper(result).reversed();
+
+        assert_eq!(reversed.0.items, vec![2, 3]);
+        assert!(!reversed.0.page_info.has_next_page);
+        assert!(reversed.0.page_info.has_previous_page);
+        assert_eq!(reversed.0.page_info.start_cursor.as_deref(), Some("2"));
     }
 }
@@ -0,0 +1,195 @@
+use std::{collections::HashSet, time::Duration};
+
+use async_graphql::{Error, Result, SimpleObject};
+use bson::Uuid;
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::DaprSettings, telemetry::current_trace_carrier, wishlist::Wishlist};
+
+/// Type of a wishlist domain event, each type is published on its own topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WishlistEventType {
+    /// Wishlist was created.
+    Created,
+    /// Name or product variants of a wishlist changed.
+    Updated,
+    /// Wishlist was deleted.
+    Deleted,
+}
+
+/// Data of a wishlist domain event.
+///
+/// Dapr wraps this data in a CloudEvent envelope before passing it to subscribers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject)]
+pub struct WishlistEventData {
+    /// UUID of the wishlist.
+    pub id: Uuid,
+    /// UUID of the user owning the wishlist.
+    pub user_id: Uuid,
+    /// Name of the wishlist after the change.
+    pub name: String,
+    /// UUIDs of product variants added to the wishlist by the change.
+    pub added_product_variant_ids: Vec<Uuid>,
+    /// UUIDs of product variants removed from the wishlist by the change.
+    pub removed_product_variant_ids: Vec<Uuid>,
+}
+
+impl WishlistEventData {
+    /// Describes the change between the state of a wishlist before and after a mutation.
+    ///
+    /// * `before` - Wishlist before the mutation, `None` if the wishlist was created.
+    /// * `after` - Wishlist after the mutation, `None` if the wishlist was deleted.
+    pub fn from_change(before: Option<&Wishlist>, after: Option<&Wishlist>) -> Option<Self> {
+        let wishlist = after.or(before)?;
+        let product_variant_ids = |w: Option<&Wishlist>| -> HashSet<Uuid> {
+            w.map(|w| w.internal_product_variants.iter().map(|p| p._id).collect())
+                .unwrap_or_default()
+        };
+        let before_ids = product_variant_ids(before);
+        let after_ids = product_variant_ids(after);
+        Some(Self {
+            id: wishlist._id,
+            user_id: wishlist.user._id,
+            name: wishlist.name.clone(),
+            added_product_variant_ids: after_ids.difference(&before_ids).copied().collect(),
+            removed_product_variant_ids: before_ids.difference(&after_ids).copied().collect(),
+        })
+    }
+}
+
+/// Publishes wishlist domain events to the publish API of the Dapr sidecar.
+#[derive(Clone, Debug)]
+pub struct EventPublisher {
+    client: reqwest::Client,
+    /// Base URL of the Dapr sidecar HTTP API, e.g. `http://localhost:3500`.
+    dapr_http_endpoint: String,
+    /// Name of the Dapr pub/sub component.
+    pubsub_name: String,
+}
+
+impl EventPublisher {
+    /// Creates a publisher for the Dapr sidecar reachable under `dapr_http_endpoint`.
+    ///
+    /// Any HTTP endpoint implementing the Dapr publish API can be used, which allows stand-ins in tests.
+    ///
+    /// * `dapr_http_endpoint` - Base URL of the Dapr sidecar HTTP API.
+    /// * `pubsub_name` - Name of the Dapr pub/sub component.
+    /// * `timeout` - Timeout of each request including connecting, a hung sidecar does not block the outbox drain task.
+    pub fn new(
+        dapr_http_endpoint: impl Into<String>,
+        pubsub_name: impl Into<String>,
+        timeout: Duration,
+    ) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()
+            .expect("Building HTTP client of the Dapr sidecar failed.");
+        Self {
+            client,
+            dapr_http_endpoint: dapr_http_endpoint.into().trim_end_matches('/').to_string(),
+            pubsub_name: pubsub_name.into(),
+        }
+    }
+
+    /// Creates a publisher from the Dapr settings.
+    pub fn from_settings(settings: &DaprSettings) -> Self {
+        Self::new(
+            settings.resolved_http_endpoint(),
+            settings.pubsub_name.clone(),
+            settings.request_timeout(),
+        )
+    }
+
+    /// Publishes event data under a topic.
+    ///
+    /// The current trace context is passed in the `traceparent` header, which Dapr adds to the CloudEvent.
+    ///
+    /// * `topic` - Topic to publish the event on.
+    /// * `data` - Event data, serialized as JSON.
+    pub async fn publish<T: Serialize>(&self, topic: &str, data: &T) -> Result<()> {
+        let url = format!(
+            "{}/v1.0/publish/{}/{}",
+            self.dapr_http_endpoint, self.pubsub_name, topic
+        );
+        let mut request = self.client.post(&url).json(data);
+        for (name, value) in current_trace_carrier() {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => {
+                let message = format!(
+                    "Publishing event of topic: `{}` failed with status: `{}`.",
+                    topic,
+                    response.status()
+                );
+                Err(Error::new(message))
+            }
+            Err(error) if error.is_timeout() => {
+                let message = format!(
+                    "Publishing event of topic: `{}` failed, Dapr sidecar did not respond in time.",
+                    topic
+                );
+                Err(Error::new(message))
+            }
+            Err(_) => {
+                let message = format!(
+                    "Publishing event of topic: `{}` failed, Dapr sidecar is not reachable.",
+                    topic
+                );
+                Err(Error::new(message))
+            }
+        }
+    }
+
+    /// Checks the health of the Dapr sidecar with its health API.
+    pub async fn check_sidecar_health(&self) -> Result<()> {
+        let url = format!("{}/v1.0/healthz", self.dapr_http_endpoint);
+        match self.client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => {
+                let message = format!(
+                    "Dapr sidecar is unhealthy with status: `{}`.",
+                    response.status()
+                );
+                Err(Error::new(message))
+            }
+            Err(error) if error.is_timeout() => {
+                Err(Error::new("Dapr sidecar did not respond in time."))
+            }
+            Err(_) => Err(Error::new("Dapr sidecar is not reachable.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Router};
+
+    use super::*;
+    use crate::integration_tests::serve;
+
+    #[tokio::test]
+    async fn fails_publishing_to_hung_sidecar_after_timeout() {
+        let hung_sidecar = Router::new().route(
+            "/v1.0/publish/:pubsub/*topic",
+            post(|| tokio::time::sleep(Duration::from_secs(60))),
+        );
+        let event_publisher = EventPublisher::new(
+            format!("http://{}", serve(hung_sidecar)),
+            "pubsub",
+            Duration::from_millis(100),
+        );
+
+        let error = event_publisher
+            .publish("wishlist/wishlist/created", &"data")
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.message,
+            "Publishing event of topic: `wishlist/wishlist/created` failed, Dapr sidecar did not respond in time."
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use bson::doc;
+use mongodb::Database;
+use serde::Serialize;
+
+use crate::{event_publisher::EventPublisher, settings::HealthSettings};
+
+/// State of the health endpoints containing the checked dependencies.
+#[derive(Clone)]
+pub struct HealthState {
+    pub db_client: Database,
+    pub event_publisher: EventPublisher,
+    pub settings: HealthSettings,
+}
+
+/// Status of the service or of a dependency.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Health report of the service.
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    /// `UP` if all checked dependencies are up.
+    pub status: HealthStatus,
+    /// Reports of the checked dependencies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<DependencyReports>,
+}
+
+/// Health reports of the dependencies checked for readiness.
+#[derive(Serialize, Debug)]
+pub struct DependencyReports {
+    pub mongodb: DependencyReport,
+    pub dapr: DependencyReport,
+}
+
+/// Health report of a dependency.
+#[derive(Serialize, Debug)]
+pub struct DependencyReport {
+    pub status: HealthStatus,
+    /// Milliseconds the check took.
+    pub latency_ms: u128,
+    /// Reason why the dependency is down.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// HTTP endpoint for liveness probes.
+///
+/// Reports the process as up without checking dependencies, so that unavailable dependencies do not restart the service.
+pub async fn live() -> Json<HealthReport> {
+    Json(HealthReport {
+        status: HealthStatus::Up,
+        checks: None,
+    })
+}
+
+/// HTTP endpoint for readiness probes.
+///
+/// Pings MongoDB and the Dapr sidecar concurrently and answers with status `503` if one of them is down.
+pub async fn ready(State(state): State<HealthState>) -> (StatusCode, Json<HealthReport>) {
+    let mongodb_check = check_dependency(
+        Duration::from_millis(state.settings.mongodb_timeout_ms),
+        async {
+            state
+                .db_client
+                .run_command(doc! {"ping": 1}, None)
+                .await
+                .map(|_| ())
+                .map_err(|error| error.to_string())
+        },
+    );
+    let dapr_check = check_dependency(
+        Duration::from_millis(state.settings.dapr_timeout_ms),
+        async {
+            state
+                .event_publisher
+                .check_sidecar_health()
+                .await
+                .map_err(|error| error.message)
+        },
+    );
+    let (mongodb, dapr) = tokio::join!(mongodb_check, dapr_check);
+    let status = match mongodb.status == HealthStatus::Up && dapr.status == HealthStatus::Up {
+        true => HealthStatus::Up,
+        false => HealthStatus::Down,
+    };
+    let status_code = match status {
+        HealthStatus::Up => StatusCode::OK,
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let report = HealthReport {
+        status,
+        checks: Some(DependencyReports { mongodb, dapr }),
+    };
+    (status_code, Json(report))
+}
+
+/// Runs the check of a dependency and measures its latency.
+///
+/// * `timeout` - Duration after which the dependency is reported as down.
+/// * `check` - Check that fails with a reason if the dependency is down.
+async fn check_dependency(
+    timeout: Duration,
+    check: impl Future<Output = Result<(), String>>,
+) -> DependencyReport {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Check timed out after {} ms.", timeout.as_millis())),
+    };
+    let latency_ms = start.elapsed().as_millis();
+    match result {
+        Ok(()) => DependencyReport {
+            status: HealthStatus::Up,
+            latency_ms,
+            error: None,
+        },
+        Err(error) => DependencyReport {
+            status: HealthStatus::Down,
+            latency_ms,
+            error: Some(error),
+        },
+    }
+}
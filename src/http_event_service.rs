@@ -1,10 +1,28 @@
-use axum::{debug_handler, extract::State, http::StatusCode, Json};
+use std::collections::HashMap;
+
+use axum::{
+    body::Bytes,
+    debug_handler,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
 use bson::Uuid;
-use log::info;
-use mongodb::Collection;
+use log::{info, warn};
+use mongodb::bson::DateTime;
+use opentelemetry::{
+    trace::{FutureExt, SpanKind, Status, TraceContextExt},
+    Context, KeyValue,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{foreign_types::ProductVariant, user::User};
+use crate::{
+    event_publisher::EventPublisher,
+    metrics::Metrics,
+    repository::Repositories,
+    settings::TopicSettings,
+    telemetry::{context_from_carrier, context_from_headers, start_span},
+};
 
 /// Data to send to Dapr in order to describe a subscription.
 #[derive(Serialize)]
@@ -13,26 +31,113 @@ pub struct Pubsub {
     pub pubsubname: String,
     pub topic: String,
     pub route: String,
+    #[serde(rename(serialize = "deadLetterTopic"))]
+    pub dead_letter_topic: String,
 }
 
 /// Reponse data to send to Dapr when receiving an event.
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 pub struct TopicEventResponse {
-    pub status: u8,
+    pub status: TopicEventStatus,
 }
 
-/// Default status is `0` -> Ok, according to Dapr specs.
-impl Default for TopicEventResponse {
-    fn default() -> Self {
-        Self { status: 0 }
+/// Status of a received event, according to Dapr specs.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TopicEventStatus {
+    /// Event was processed successfully.
+    #[default]
+    Success,
+    /// Event processing failed temporarily, Dapr redelivers the event.
+    Retry,
+    /// Event can not be processed, Dapr drops the event.
+    Drop,
+}
+
+impl TopicEventStatus {
+    /// Status as used in metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Retry => "retry",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+impl From<TopicEventStatus> for TopicEventResponse {
+    fn from(status: TopicEventStatus) -> Self {
+        Self { status }
+    }
+}
+
+/// Reason why an event could not be processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventProcessingError {
+    /// Topic is not handled by this service, the event is dropped.
+    UnknownTopic(String),
+    /// Payload can not be parsed, the event is forwarded to the dead-letter topic and dropped.
+    MalformedPayload(String),
+    /// Processing failed temporarily, e.g. because MongoDB is not reachable, the event is redelivered.
+    Transient(String),
+}
+
+impl EventProcessingError {
+    /// Dapr status that describes how the event is handled further.
+    pub fn status(&self) -> TopicEventStatus {
+        match self {
+            Self::UnknownTopic(_) => TopicEventStatus::Drop,
+            Self::MalformedPayload(_) => TopicEventStatus::Drop,
+            Self::Transient(_) => TopicEventStatus::Retry,
+        }
+    }
+
+    /// Human readable reason of the failure.
+    pub fn reason(&self) -> &str {
+        match self {
+            Self::UnknownTopic(reason) => reason,
+            Self::MalformedPayload(reason) => reason,
+            Self::Transient(reason) => reason,
+        }
     }
 }
 
+/// Result of processing an event.
+pub type EventProcessingResult = Result<(), EventProcessingError>;
+
 /// Relevant part of Dapr event wrapped in a CloudEnvelope.
+///
+/// The data is kept unparsed, as its shape depends on the topic.
 #[derive(Deserialize, Debug)]
 pub struct Event {
+    /// CloudEvent id, identical for redeliveries of the same event.
+    pub id: String,
     pub topic: String,
-    pub data: EventData,
+    #[serde(default)]
+    pub data: serde_json::Value,
+    /// W3C trace context extension of the CloudEvent, set by Dapr from the trace of the publisher.
+    #[serde(default)]
+    pub traceparent: Option<String>,
+    /// W3C trace state extension of the CloudEvent.
+    #[serde(default)]
+    pub tracestate: Option<String>,
+}
+
+impl Event {
+    /// Trace context of the publisher, `None` if the CloudEvent does not carry one.
+    pub fn trace_context(&self) -> Option<Context> {
+        let traceparent = self.traceparent.clone()?;
+        let mut carrier = HashMap::from([("traceparent".to_string(), traceparent)]);
+        if let Some(tracestate) = &self.tracestate {
+            carrier.insert("tracestate".to_string(), tracestate.clone());
+        }
+        Some(context_from_carrier(&carrier))
+    }
+
+    /// Parses the data of an event of a subscribed topic.
+    pub fn parse_data(&self) -> serde_json::Result<EventData> {
+        serde_json::from_value(self.data.clone())
+    }
 }
 
 /// Relevant part of Dapr event.data.
@@ -41,70 +146,332 @@ pub struct EventData {
     pub id: Uuid,
 }
 
-/// Service state containing database connections.
+/// Record of an event that was processed successfully.
+///
+/// Used to acknowledge redeliveries of an event without processing it again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProcessedEvent {
+    /// CloudEvent id.
+    pub _id: String,
+    /// Topic of the event.
+    pub topic: String,
+    /// Timestamp when the event was processed.
+    pub processed_at: DateTime,
+}
+
+/// Record of an event that could not be processed, kept for inspection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailedEvent {
+    /// Failed event record UUID.
+    pub _id: Uuid,
+    /// CloudEvent id, `None` if the payload does not contain one.
+    pub event_id: Option<String>,
+    /// Topic of the event, `None` if the payload does not contain one.
+    pub topic: Option<String>,
+    /// Reason of the failure.
+    pub reason: String,
+    /// Status the event was answered with.
+    pub status: TopicEventStatus,
+    /// Raw payload of the event.
+    pub payload: String,
+    /// Timestamp when processing failed.
+    pub failed_at: DateTime,
+}
+
+/// Service state containing the repositories.
 #[derive(Clone)]
 pub struct HttpEventServiceState {
-    pub product_variant_collection: Collection<ProductVariant>,
-    pub user_collection: Collection<User>,
+    pub repositories: Repositories,
+    pub event_publisher: EventPublisher,
+    pub metrics: Metrics,
+    pub pubsub_name: String,
+    pub topics: TopicSettings,
 }
 
 /// HTTP endpoint to list topic subsciptions.
-pub async fn list_topic_subscriptions() -> Result<Json<Vec<Pubsub>>, StatusCode> {
-    let pubsub_user = Pubsub {
-        pubsubname: "pubsub".to_string(),
-        topic: "user/user/created".to_string(),
-        route: "/on-topic-event".to_string(),
-    };
-    let pubsub_product_variant = Pubsub {
-        pubsubname: "pubsub".to_string(),
-        topic: "catalog/product-variant/created".to_string(),
-        route: "/on-topic-event".to_string(),
-    };
-    Ok(Json(vec![pubsub_user, pubsub_product_variant]))
+pub async fn list_topic_subscriptions(
+    State(state): State<HttpEventServiceState>,
+) -> Result<Json<Vec<Pubsub>>, StatusCode> {
+    let pubsubs = state
+        .topics
+        .subscribed()
+        .iter()
+        .map(|topic| Pubsub {
+            pubsubname: state.pubsub_name.clone(),
+            topic: topic.to_string(),
+            route: "/on-topic-event".to_string(),
+            dead_letter_topic: state.topics.dead_letter.clone(),
+        })
+        .collect();
+    Ok(Json(pubsubs))
 }
 
 /// HTTP endpoint to receive events.
+///
+/// Always answers with HTTP status `200`, the Dapr status in the body describes how the event is handled further.
+/// The event is processed in the trace of its CloudEvent `traceparent`, falling back to the `traceparent` header.
 #[debug_handler(state = HttpEventServiceState)]
 pub async fn on_topic_event(
     State(state): State<HttpEventServiceState>,
-    Json(event): Json<Event>,
-) -> Result<Json<TopicEventResponse>, StatusCode> {
-    info!("{:?}", event);
+    headers: HeaderMap,
+    body: Bytes,
+) -> Json<TopicEventResponse> {
+    let maybe_event = serde_json::from_slice::<Event>(&body);
+    let mut attributes = vec![KeyValue::new("messaging.system", "dapr")];
+    let parent_context = match &maybe_event {
+        Ok(event) => {
+            attributes.push(KeyValue::new(
+                "messaging.destination.name",
+                event.topic.clone(),
+            ));
+            attributes.push(KeyValue::new("messaging.message.id", event.id.clone()));
+            event
+                .trace_context()
+                .unwrap_or_else(|| context_from_headers(&headers))
+        }
+        Err(_) => context_from_headers(&headers),
+    };
+    let trace_context = start_span(
+        "on_topic_event",
+        SpanKind::Consumer,
+        attributes,
+        &parent_context,
+    );
+    let status = receive_event(&state, maybe_event, &body)
+        .with_context(trace_context.clone())
+        .await;
+    if status != TopicEventStatus::Success {
+        trace_context
+            .span()
+            .set_status(Status::error(status.as_str()));
+    }
+    trace_context.span().end();
+    Json(status.into())
+}
 
+/// Processes a received event or forwards it to the dead-letter topic if it could not be parsed.
+///
+/// Events of topics the service is not subscribed to are dropped before their data is parsed.
+///
+/// Returns the Dapr status the event is answered with.
+async fn receive_event(
+    state: &HttpEventServiceState,
+    maybe_event: serde_json::Result<Event>,
+    body: &Bytes,
+) -> TopicEventStatus {
+    let (topic, result) = match maybe_event {
+        Ok(event) => {
+            info!(
+                "Received event of id: `{}` and topic: `{}`.",
+                event.id, event.topic
+            );
+            let topic = state
+                .topics
+                .subscribed()
+                .into_iter()
+                .find(|topic| *topic == event.topic);
+            let result = match (topic, event.parse_data()) {
+                (None, _) => {
+                    let message = format!(
+                        "Event of topic: `{}` is not a handleable by this service.",
+                        event.topic
+                    );
+                    Err(EventProcessingError::UnknownTopic(message))
+                }
+                (Some(_), Ok(data)) => handle_event(state, &event, &data).await,
+                (Some(_), Err(error)) => {
+                    let reason = format!("Event data could not be parsed: {}", error);
+                    forward_to_dead_letter_topic(state, body, reason).await
+                }
+            };
+            (topic, result)
+        }
+        Err(error) => {
+            let reason = format!("Event payload could not be parsed: {}", error);
+            (
+                None,
+                forward_to_dead_letter_topic(state, body, reason).await,
+            )
+        }
+    };
+    let status = match &result {
+        Ok(()) => TopicEventStatus::Success,
+        Err(error) => error.status(),
+    };
+    state.metrics.record_dapr_event(topic, status.as_str());
+    if let Err(error) = result {
+        warn!(
+            "Processing event failed with status `{:?}`: {}",
+            error.status(),
+            error.reason()
+        );
+        record_failed_event(&state.repositories, body, &error).await;
+    }
+    status
+}
+
+/// Processes an event unless an event of the same CloudEvent id was already processed.
+async fn handle_event(
+    state: &HttpEventServiceState,
+    event: &Event,
+    data: &EventData,
+) -> EventProcessingResult {
+    let events = &state.repositories.events;
+    if events
+        .is_processed(&event.id)
+        .await
+        .map_err(transient_error)?
+    {
+        info!("Event of id: `{}` was already processed.", event.id);
+        return Ok(());
+    }
+    process_event(state, event, data).await?;
+    events
+        .mark_processed(&event.id, &event.topic)
+        .await
+        .map_err(transient_error)
+}
+
+/// Processes an event according to its topic.
+async fn process_event(
+    state: &HttpEventServiceState,
+    event: &Event,
+    data: &EventData,
+) -> EventProcessingResult {
+    let topics = &state.topics;
+    let repositories = &state.repositories;
     match event.topic.as_str() {
-        "catalog/product-variant/created" => {
-            add_product_variant_to_mongodb(state.product_variant_collection, event.data.id).await?
+        topic if topic == topics.product_variant_created => {
+            add_product_variant(repositories, data.id).await
+        }
+        topic
+            if topic == topics.product_variant_archived
+                || topic == topics.product_variant_deleted =>
+        {
+            remove_product_variant(repositories, data.id).await
+        }
+        topic if topic == topics.user_created => add_user(repositories, data.id).await,
+        topic if topic == topics.user_archived || topic == topics.user_deleted => {
+            remove_user(repositories, data.id).await
         }
-        "user/user/created" => add_user_to_mongodb(state.user_collection, event.data.id).await?,
         _ => {
-            // TODO: This message can be used for further Error visibility.
-            let _message = format!(
+            let message = format!(
                 "Event of topic: `{}` is not a handleable by this service.",
                 event.topic.as_str()
             );
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(EventProcessingError::UnknownTopic(message))
         }
     }
-    Ok(Json(TopicEventResponse::default()))
 }
 
-/// Add a newly created product variant to MongoDB.
-pub async fn add_product_variant_to_mongodb(
-    collection: Collection<ProductVariant>,
-    id: Uuid,
-) -> Result<(), StatusCode> {
-    let product_variant = ProductVariant { _id: id };
-    match collection.insert_one(product_variant, None).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// Forwards the raw payload of a malformed event to the dead-letter topic.
+///
+/// Fails transiently if forwarding is not possible, so that the event is not lost.
+async fn forward_to_dead_letter_topic(
+    state: &HttpEventServiceState,
+    body: &Bytes,
+    reason: String,
+) -> EventProcessingResult {
+    let payload = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(value) => value,
+        Err(_) => serde_json::Value::String(String::from_utf8_lossy(body).into_owned()),
+    };
+    match state
+        .event_publisher
+        .publish(&state.topics.dead_letter, &payload)
+        .await
+    {
+        Ok(()) => Err(EventProcessingError::MalformedPayload(reason)),
+        Err(error) => {
+            let message = format!(
+                "{} Forwarding to dead-letter topic failed: {}",
+                reason, error.message
+            );
+            Err(EventProcessingError::Transient(message))
+        }
     }
 }
 
-/// Add a newly created user to MongoDB.
-pub async fn add_user_to_mongodb(collection: Collection<User>, id: Uuid) -> Result<(), StatusCode> {
-    let user = User { _id: id };
-    match collection.insert_one(user, None).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// Persists a failed event for inspection.
+///
+/// Only logs if persisting fails, the response to Dapr does not depend on the record.
+async fn record_failed_event(
+    repositories: &Repositories,
+    body: &Bytes,
+    error: &EventProcessingError,
+) {
+    let maybe_payload = serde_json::from_slice::<serde_json::Value>(body).ok();
+    let envelope_field = |field: &str| -> Option<String> {
+        maybe_payload
+            .as_ref()
+            .and_then(|payload| payload.get(field))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+    };
+    let failed_event = FailedEvent {
+        _id: Uuid::new(),
+        event_id: envelope_field("id"),
+        topic: envelope_field("topic"),
+        reason: error.reason().to_string(),
+        status: error.status(),
+        payload: String::from_utf8_lossy(body).into_owned(),
+        failed_at: DateTime::now(),
+    };
+    if let Err(error) = repositories.events.record_failed(failed_event).await {
+        warn!("{}", error.message);
     }
 }
+
+/// Converts a storage error to a transient processing error, the event is redelivered.
+fn transient_error(error: async_graphql::Error) -> EventProcessingError {
+    EventProcessingError::Transient(format!("Processing event failed: {}", error.message))
+}
+
+/// Add a newly created product variant.
+///
+/// Redeliveries of the event do not fail on a duplicate product variant.
+pub async fn add_product_variant(repositories: &Repositories, id: Uuid) -> EventProcessingResult {
+    repositories
+        .product_variants
+        .insert(id)
+        .await
+        .map_err(transient_error)
+}
+
+/// Remove an archived or deleted product variant and prune it from all wishlists.
+///
+/// Wishlists are pruned first, so that a failed attempt can be retried without leaving dangling references.
+pub async fn remove_product_variant(
+    repositories: &Repositories,
+    id: Uuid,
+) -> EventProcessingResult {
+    repositories
+        .wishlists
+        .remove_product_variant(id)
+        .await
+        .map_err(transient_error)?;
+    repositories
+        .product_variants
+        .delete(id)
+        .await
+        .map_err(transient_error)
+}
+
+/// Add a newly created user.
+///
+/// Redeliveries of the event do not fail on a duplicate user.
+pub async fn add_user(repositories: &Repositories, id: Uuid) -> EventProcessingResult {
+    repositories.users.insert(id).await.map_err(transient_error)
+}
+
+/// Remove a deleted or archived user, all wishlists owned by the user and the memberships of the user.
+///
+/// Wishlists and memberships are removed first, so that a failed attempt can be retried without orphaning them.
+pub async fn remove_user(repositories: &Repositories, id: Uuid) -> EventProcessingResult {
+    repositories
+        .wishlists
+        .remove_user(id)
+        .await
+        .map_err(transient_error)?;
+    repositories.users.delete(id).await.map_err(transient_error)
+}
@@ -0,0 +1,830 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_graphql::{Error, MaybeUndefined, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bson::{Bson, DateTime, Document, Uuid};
+use mongodb_cursor_pagination::{Edge, FindResult, PageInfo};
+
+use crate::{
+    base_connection::{BaseConnection, CursorArguments, FindResultWrapper, DEFAULT_PAGE_SIZE},
+    event_publisher::WishlistEventType,
+    foreign_types::ProductVariant,
+    http_event_service::{FailedEvent, ProcessedEvent},
+    outbox::{OutboxBacklog, OutboxEntry},
+    repository::{
+        event_states, EventRecordRepository, ProductVariantRepository, UserRepository,
+        WishlistModification, WishlistPageQuery, WishlistRepository,
+    },
+    settings::TopicSettings,
+    user::User,
+    wishlist::{Wishlist, WishlistVisibility},
+    wishlist_member::WishlistMemberStatus,
+};
+
+/// Repositories stored in memory, which behave like the MongoDB repositories.
+///
+/// Used to run the GraphQL schema and the Dapr event endpoints in tests without external services.
+/// Clones share the same storage.
+#[derive(Clone, Default)]
+pub struct InMemoryRepository {
+    state: Arc<Mutex<InMemoryState>>,
+    topics: TopicSettings,
+}
+
+/// Stored documents, equivalent to the MongoDB collections.
+#[derive(Default)]
+struct InMemoryState {
+    wishlists: HashMap<Uuid, Wishlist>,
+    users: HashSet<Uuid>,
+    product_variants: HashSet<Uuid>,
+    outbox: Vec<OutboxEntry>,
+    processed_events: HashMap<String, ProcessedEvent>,
+    failed_events: Vec<FailedEvent>,
+}
+
+impl InMemoryRepository {
+    /// Creates empty repositories that write events for the given topics to the outbox.
+    pub fn new(topics: TopicSettings) -> Self {
+        Self {
+            state: Arc::default(),
+            topics,
+        }
+    }
+
+    /// Entries written to the outbox, in the order they were written.
+    pub fn outbox(&self) -> Vec<OutboxEntry> {
+        self.lock().outbox.clone()
+    }
+
+    /// Records of events that could not be processed.
+    pub fn failed_events(&self) -> Vec<FailedEvent> {
+        self.lock().failed_events.clone()
+    }
+
+    /// Locks the storage, a panic of another test thread does not poison it for further use.
+    fn lock(&self) -> MutexGuard<'_, InMemoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl InMemoryState {
+    /// Writes the change of a wishlist to the outbox.
+    fn enqueue(&mut self, topic: &str, before: Option<&Wishlist>, after: Option<&Wishlist>) {
+        if let Some(entry) = OutboxEntry::from_change(topic, before, after) {
+            self.outbox.push(entry);
+        }
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl WishlistRepository for InMemoryRepository {
+    async fn find(&self, id: Uuid) -> Result<Wishlist> {
+        match self.lock().wishlists.get(&id) {
+            Some(wishlist) if wishlist.deleted_at.is_none() => Ok(wishlist.clone()),
+            _ => {
+                let message = format!("Wishlist with UUID: `{}` not found.", id);
+                Err(Error::new(message))
+            }
+        }
+    }
+
+    async fn find_trashed(&self, id: Uuid) -> Result<Wishlist> {
+        match self.lock().wishlists.get(&id) {
+            Some(wishlist) if wishlist.deleted_at.is_some() => Ok(wishlist.clone()),
+            _ => {
+                let message = format!("Trashed wishlist with UUID: `{}` not found.", id);
+                Err(Error::new(message))
+            }
+        }
+    }
+
+    async fn find_shared(&self, share_token: &str) -> Result<Wishlist> {
+        self.lock()
+            .wishlists
+            .values()
+            .find(|wishlist| {
+                wishlist.share_token.as_deref() == Some(share_token)
+                    && wishlist.deleted_at.is_none()
+                    && wishlist.visibility != WishlistVisibility::Private
+            })
+            .cloned()
+            .ok_or_else(|| Error::new("Wishlist for share token not found or sharing was revoked."))
+    }
+
+    async fn find_trashed_of_user(&self, user_id: Uuid) -> Result<Vec<Wishlist>> {
+        let mut wishlists: Vec<Wishlist> = self
+            .lock()
+            .wishlists
+            .values()
+            .filter(|wishlist| wishlist.user._id == user_id && wishlist.deleted_at.is_some())
+            .cloned()
+            .collect();
+        wishlists.sort_by(|x, y| {
+            y.deleted_at
+                .cmp(&x.deleted_at)
+                .then_with(|| y._id.bytes().cmp(&x._id.bytes()))
+        });
+        Ok(wishlists)
+    }
+
+    async fn paginate_of_user(&self, query: WishlistPageQuery) -> Result<BaseConnection<Wishlist>> {
+        let wishlists: Vec<Wishlist> = self
+            .lock()
+            .wishlists
+            .values()
+            .filter(|wishlist| {
+                wishlist.user._id == query.user_id
+                    && wishlist.deleted_at.is_none()
+                    && query.reader_id.is_none_or(|reader_id| {
+                        wishlist.visibility == WishlistVisibility::Public
+                            || wishlist.accepted_member(reader_id).is_some()
+                    })
+            })
+            .cloned()
+            .collect();
+        paginate(
+            wishlists,
+            query.order.sort_doc(),
+            query.skip,
+            query.cursor_arguments,
+        )
+    }
+
+    async fn create(&self, wishlist: &Wishlist) -> Result<Wishlist> {
+        let mut state = self.lock();
+        if state.wishlists.contains_key(&wishlist._id) {
+            return Err(Error::new("Adding wishlist failed in MongoDB."));
+        }
+        state.wishlists.insert(wishlist._id, wishlist.clone());
+        let topic = self.topics.wishlist_topic(WishlistEventType::Created);
+        state.enqueue(topic, None, Some(wishlist));
+        Ok(wishlist.clone())
+    }
+
+    async fn apply(
+        &self,
+        before: &Wishlist,
+        modification: WishlistModification,
+    ) -> Result<Option<Wishlist>> {
+        let maybe_event_type = modification.event_type();
+        let mut state = self.lock();
+        let after = match state.wishlists.get_mut(&before._id) {
+            Some(wishlist) => match modify(wishlist, modification) {
+                true => wishlist.clone(),
+                false => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        if let Some(event_type) = maybe_event_type {
+            let (event_before, event_after) = event_states(event_type, before, &after);
+            state.enqueue(
+                self.topics.wishlist_topic(event_type),
+                event_before,
+                event_after,
+            );
+        }
+        Ok(Some(after))
+    }
+
+    async fn remove_product_variant(&self, product_variant_id: Uuid) -> Result<()> {
+        let current_timestamp = DateTime::now();
+        let mut state = self.lock();
+        let ids: Vec<Uuid> = state
+            .wishlists
+            .values()
+            .filter(|wishlist| wishlist.contains_product_variant(product_variant_id))
+            .map(|wishlist| wishlist._id)
+            .collect();
+        for id in ids {
+            let Some(wishlist) = state.wishlists.get_mut(&id) else {
+                continue;
+            };
+            let before = wishlist.clone();
+            wishlist
+                .internal_product_variants
+                .retain(|item| item._id != product_variant_id);
+            wishlist.last_updated_at = current_timestamp;
+            wishlist.version += 1;
+            let after = wishlist.clone();
+            if after.deleted_at.is_none() {
+                let topic = self.topics.wishlist_topic(WishlistEventType::Updated);
+                state.enqueue(topic, Some(&before), Some(&after));
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_user(&self, user_id: Uuid) -> Result<()> {
+        let mut state = self.lock();
+        let owned_ids: Vec<Uuid> = state
+            .wishlists
+            .values()
+            .filter(|wishlist| wishlist.user._id == user_id)
+            .map(|wishlist| wishlist._id)
+            .collect();
+        for id in owned_ids {
+            if let Some(wishlist) = state.wishlists.remove(&id) {
+                if wishlist.deleted_at.is_none() {
+                    let topic = self.topics.wishlist_topic(WishlistEventType::Deleted);
+                    state.enqueue(topic, Some(&wishlist), None);
+                }
+            }
+        }
+        let member_ids: Vec<Uuid> = state
+            .wishlists
+            .values()
+            .filter(|wishlist| wishlist.is_member(user_id))
+            .map(|wishlist| wishlist._id)
+            .collect();
+        for id in member_ids {
+            let Some(wishlist) = state.wishlists.get_mut(&id) else {
+                continue;
+            };
+            let before = wishlist.clone();
+            wishlist.members.retain(|member| member.user._id != user_id);
+            wishlist.version += 1;
+            let after = wishlist.clone();
+            if after.deleted_at.is_none() {
+                let topic = self.topics.wishlist_topic(WishlistEventType::Updated);
+                state.enqueue(topic, Some(&before), Some(&after));
+            }
+        }
+        Ok(())
+    }
+
+    async fn outbox_backlog(&self, first: Option<u32>) -> Result<OutboxBacklog> {
+        let state = self.lock();
+        let pending = state
+            .outbox
+            .iter()
+            .filter(|entry| entry.delivered_at.is_none());
+        let total_count = pending.clone().count() as u64;
+        let nodes = pending
+            .take(first.map_or(usize::MAX, |first| first as usize))
+            .cloned()
+            .collect();
+        Ok(OutboxBacklog { nodes, total_count })
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl UserRepository for InMemoryRepository {
+    async fn find(&self, id: Uuid) -> Result<User> {
+        match self.lock().users.contains(&id) {
+            true => Ok(User { _id: id }),
+            false => {
+                let message = format!("User with UUID: `{}` not found.", id);
+                Err(Error::new(message))
+            }
+        }
+    }
+
+    async fn insert(&self, id: Uuid) -> Result<()> {
+        self.lock().users.insert(id);
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<()> {
+        self.lock().users.remove(&id);
+        Ok(())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl ProductVariantRepository for InMemoryRepository {
+    async fn find_many(&self, ids: &HashSet<Uuid>) -> Result<Vec<ProductVariant>> {
+        let state = self.lock();
+        Ok(ids
+            .iter()
+            .filter(|id| state.product_variants.contains(id))
+            .map(|id| ProductVariant { _id: *id })
+            .collect())
+    }
+
+    async fn insert(&self, id: Uuid) -> Result<()> {
+        self.lock().product_variants.insert(id);
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<()> {
+        self.lock().product_variants.remove(&id);
+        Ok(())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl EventRecordRepository for InMemoryRepository {
+    async fn is_processed(&self, event_id: &str) -> Result<bool> {
+        Ok(self.lock().processed_events.contains_key(event_id))
+    }
+
+    async fn mark_processed(&self, event_id: &str, topic: &str) -> Result<()> {
+        self.lock()
+            .processed_events
+            .entry(event_id.to_string())
+            .or_insert_with(|| ProcessedEvent {
+                _id: event_id.to_string(),
+                topic: topic.to_string(),
+                processed_at: DateTime::now(),
+            });
+        Ok(())
+    }
+
+    async fn record_failed(&self, failed_event: FailedEvent) -> Result<()> {
+        self.lock().failed_events.push(failed_event);
+        Ok(())
+    }
+}
+
+/// Applies a modification like the MongoDB update of the modification does.
+///
+/// Returns `false` without changing the wishlist if it does not meet the precondition of the modification.
+fn modify(wishlist: &mut Wishlist, modification: WishlistModification) -> bool {
+    if wishlist.deleted_at.is_some() != modification.applies_to_trashed() {
+        return false;
+    }
+    let increases_version = modification.event_type().is_some();
+    match modification {
+        WishlistModification::Update {
+            expected_version,
+            name,
+            items,
+            visibility,
+            timestamp,
+        } => {
+            if wishlist.version != expected_version {
+                return false;
+            }
+            if let Some(definitely_items) = items {
+                wishlist.internal_product_variants = definitely_items;
+            }
+            if let Some(definitely_name) = name {
+                wishlist.name = definitely_name;
+            }
+            if let Some(definitely_visibility) = visibility {
+                wishlist.visibility = definitely_visibility;
+            }
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::AddItems { items, timestamp } => {
+            let new_items: Vec<_> = items
+                .into_iter()
+                .filter(|item| !wishlist.contains_product_variant(item._id))
+                .collect();
+            wishlist.internal_product_variants.extend(new_items);
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::RemoveItems {
+            product_variant_ids,
+            timestamp,
+        } => {
+            wishlist
+                .internal_product_variants
+                .retain(|item| !product_variant_ids.contains(&item._id));
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::UpdateItem {
+            product_variant_id,
+            note,
+            quantity,
+            priority,
+            timestamp,
+        } => {
+            let Some(item) = wishlist
+                .internal_product_variants
+                .iter_mut()
+                .find(|item| item._id == product_variant_id)
+            else {
+                return false;
+            };
+            match note {
+                MaybeUndefined::Value(definitely_note) => item.note = Some(definitely_note),
+                MaybeUndefined::Null => item.note = None,
+                MaybeUndefined::Undefined => (),
+            }
+            if let Some(definitely_quantity) = quantity {
+                item.quantity = definitely_quantity;
+            }
+            if let Some(definitely_priority) = priority {
+                item.priority = definitely_priority;
+            }
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::Share {
+            share_token,
+            visibility,
+            timestamp,
+        } => {
+            wishlist.share_token = Some(share_token);
+            if let Some(definitely_visibility) = visibility {
+                wishlist.visibility = definitely_visibility;
+            }
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::RevokeShare {
+            visibility,
+            timestamp,
+        } => {
+            wishlist.share_token = None;
+            if let Some(definitely_visibility) = visibility {
+                wishlist.visibility = definitely_visibility;
+            }
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::Reserve {
+            product_variant_id,
+            reservation,
+        } => {
+            match wishlist
+                .internal_product_variants
+                .iter_mut()
+                .find(|item| item._id == product_variant_id && item.reservation.is_none())
+            {
+                Some(item) => item.reservation = Some(reservation),
+                None => return false,
+            }
+        }
+        WishlistModification::Release {
+            product_variant_id,
+            user_id,
+        } => {
+            match wishlist.internal_product_variants.iter_mut().find(|item| {
+                item._id == product_variant_id
+                    && item
+                        .reservation
+                        .as_ref()
+                        .is_some_and(|reservation| reservation.user._id == user_id)
+            }) {
+                Some(item) => item.reservation = None,
+                None => return false,
+            }
+        }
+        WishlistModification::InviteMember { member } => {
+            if wishlist.is_member(member.user._id) {
+                return false;
+            }
+            wishlist.last_updated_at = member.invited_at;
+            wishlist.members.push(member);
+        }
+        WishlistModification::AcceptInvitation { user_id, timestamp } => {
+            match wishlist.members.iter_mut().find(|member| {
+                member.user._id == user_id && member.status == WishlistMemberStatus::Invited
+            }) {
+                Some(member) => {
+                    member.status = WishlistMemberStatus::Accepted;
+                    member.accepted_at = Some(timestamp);
+                }
+                None => return false,
+            }
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::RemoveMember { user_id, timestamp } => {
+            wishlist.members.retain(|member| member.user._id != user_id);
+            wishlist.last_updated_at = timestamp;
+        }
+        WishlistModification::Trash { timestamp } => wishlist.deleted_at = Some(timestamp),
+        WishlistModification::Restore => wishlist.deleted_at = None,
+    }
+    if increases_version {
+        wishlist.version += 1;
+    }
+    true
+}
+
+/// Paginates wishlists like the MongoDB cursor pagination, cursors of both are interchangeable.
+///
+/// * `wishlists` - Wishlists matching the filter of the query.
+/// * `sort` - MongoDB sort document, containing `_id` as tiebreaker.
+/// * `skip` - Amount of wishlists to skip, ignored if a cursor is set.
+/// * `cursor_arguments` - Cursor pagination arguments.
+fn paginate(
+    wishlists: Vec<Wishlist>,
+    sort: Document,
+    skip: Option<u64>,
+    cursor_arguments: CursorArguments,
+) -> Result<BaseConnection<Wishlist>> {
+    cursor_arguments.validate()?;
+    let limit = cursor_arguments
+        .first
+        .or(cursor_arguments.last)
+        .unwrap_or(DEFAULT_PAGE_SIZE) as usize;
+    let (cursor, is_previous_query, reversed) = match (
+        cursor_arguments.after,
+        cursor_arguments.before,
+        cursor_arguments.last,
+    ) {
+        (_, Some(before), _) => (Some(before), true, false),
+        (Some(after), None, _) => (Some(after), false, false),
+        (None, None, Some(_)) => (None, false, true),
+        (None, None, None) => (None, false, false),
+    };
+    let sort_keys: Vec<(String, i32)> = sort
+        .iter()
+        .map(|(key, direction)| {
+            let direction = direction.as_i32().unwrap_or(1);
+            match is_previous_query || reversed {
+                true => (key.clone(), -direction),
+                false => (key.clone(), direction),
+            }
+        })
+        .collect();
+    let total_count = wishlists.len() as u64;
+    if total_count == 0 {
+        let find_result = FindResult {
+            page_info: PageInfo::default(),
+            edges: Vec::new(),
+            total_count,
+            items: Vec::new(),
+        };
+        return Ok(FindResultWrapper(find_result).into());
+    }
+    let cursor_doc = cursor.as_deref().map(decode_cursor).transpose()?;
+    let mut documents = Vec::with_capacity(wishlists.len());
+    for wishlist in wishlists {
+        documents.push((bson::to_document(&wishlist)?, wishlist));
+    }
+    if let Some(definitely_cursor_doc) = &cursor_doc {
+        documents.retain(|(document, _)| {
+            compare_sort_values(document, definitely_cursor_doc, &sort_keys) == Ordering::Greater
+        });
+    }
+    documents.sort_by(|(x, _), (y, _)| compare_sort_values(x, y, &sort_keys));
+    let skip = skip.unwrap_or(0);
+    let has_skip = cursor_doc.is_none() && skip > 0;
+    let mut page: Vec<(Document, Wishlist)> = documents
+        .into_iter()
+        .skip(if has_skip { skip as usize } else { 0 })
+        .take(limit.saturating_add(1))
+        .collect();
+    let has_cursor = cursor_doc.is_some();
+    let (has_more, has_previous_page, has_next_page) = match has_skip {
+        true => {
+            let has_more = (page.len() as u64).saturating_add(skip) < total_count;
+            (has_more, true, has_more)
+        }
+        false => {
+            let has_more = page.len() > limit;
+            (
+                has_more,
+                (has_cursor && !is_previous_query) || (is_previous_query && has_more),
+                (!is_previous_query && has_more) || (is_previous_query && has_cursor),
+            )
+        }
+    };
+    if is_previous_query {
+        page.reverse();
+    }
+    if has_more && !is_previous_query {
+        page.pop();
+    } else if has_more {
+        page.remove(0);
+    }
+    let edges: Vec<Edge> = page
+        .iter()
+        .map(|(document, _)| Edge {
+            cursor: encode_cursor(document, &sort_keys),
+        })
+        .collect();
+    let page_info = PageInfo {
+        has_next_page,
+        has_previous_page,
+        start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+        next_cursor: edges.last().map(|edge| edge.cursor.clone()),
+    };
+    let mut find_result_wrapper = FindResultWrapper(FindResult {
+        page_info,
+        edges,
+        total_count,
+        items: page.into_iter().map(|(_, wishlist)| wishlist).collect(),
+    });
+    if has_skip {
+        find_result_wrapper = find_result_wrapper.limited(limit, skip);
+    }
+    if reversed {
+        find_result_wrapper = find_result_wrapper.reversed();
+    }
+    Ok(find_result_wrapper.into())
+}
+
+/// Compares two documents by the values of the sort keys, in the directions of the sort keys.
+fn compare_sort_values(x: &Document, y: &Document, sort_keys: &[(String, i32)]) -> Ordering {
+    sort_keys
+        .iter()
+        .map(|(key, direction)| {
+            let ordering = compare_bson(sort_value(x, key), sort_value(y, key));
+            match *direction < 0 {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Value of a sort key, which is a path like `user._id` in wishlist documents and a plain key in cursors.
+fn sort_value<'a>(document: &'a Document, key: &str) -> &'a Bson {
+    if let Some(value) = document.get(key) {
+        return value;
+    }
+    let mut value = None;
+    let mut current = Some(document);
+    for part in key.split('.') {
+        value = current.and_then(|document| document.get(part));
+        current = value.and_then(Bson::as_document);
+    }
+    value.unwrap_or(&Bson::Null)
+}
+
+/// Compares BSON values of the types wishlists are sorted by, in the order MongoDB sorts them.
+fn compare_bson(x: &Bson, y: &Bson) -> Ordering {
+    match (x, y) {
+        (Bson::String(x), Bson::String(y)) => x.cmp(y),
+        (Bson::DateTime(x), Bson::DateTime(y)) => x.cmp(y),
+        (Bson::Binary(x), Bson::Binary(y)) => x.bytes.cmp(&y.bytes),
+        (Bson::Null, Bson::Null) => Ordering::Equal,
+        (Bson::Null, _) => Ordering::Less,
+        (_, Bson::Null) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Encodes the values of the sort keys of a document as cursor, like the MongoDB pagination.
+fn encode_cursor(document: &Document, sort_keys: &[(String, i32)]) -> String {
+    let cursor_doc: Document = sort_keys
+        .iter()
+        .map(|(key, _)| (key.clone(), sort_value(document, key).clone()))
+        .collect();
+    STANDARD.encode(bson::to_vec(&cursor_doc).unwrap_or_default())
+}
+
+/// Decodes a cursor that was validated with `CursorArguments::validate`.
+fn decode_cursor(cursor: &str) -> Result<Document> {
+    let bytes = STANDARD.decode(cursor)?;
+    Ok(Document::from_reader(bytes.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integration_tests::{buyer, node_ids, user_wishlists_query, TestService};
+
+    #[tokio::test]
+    async fn creates_wishlist_and_writes_creation_event_to_outbox() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        let topics = TopicSettings::default();
+        service.add_user(user_id).await;
+        service.add_product_variant(product_variant_id).await;
+
+        let id = service
+            .create_wishlist(user_id, "Birthday", &[product_variant_id])
+            .await;
+
+        let wishlist = WishlistRepository::find(&service.repository, id)
+            .await
+            .unwrap();
+        assert_eq!(wishlist.name, "Birthday");
+        assert!(wishlist.contains_product_variant(product_variant_id));
+        let outbox = service.repository.outbox();
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].topic, topics.wishlist_created);
+        assert_eq!(outbox[0].data.id, id);
+        assert_eq!(
+            outbox[0].data.added_product_variant_ids,
+            vec![product_variant_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn paginates_wishlists_of_user_with_cursors() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let header = buyer(user_id);
+        service.add_user(user_id).await;
+        let mut ids = Vec::new();
+        for name in ["a", "b", "c"] {
+            ids.push(service.create_wishlist(user_id, name, &[]).await);
+        }
+        ids.sort_by_key(|id| id.bytes());
+        let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+
+        let data = service
+            .data(&header, user_wishlists_query(user_id, "(first: 2)"))
+            .await;
+        let first_page = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(first_page), ids[..2]);
+        assert_eq!(first_page["pageInfo"]["hasNextPage"], true);
+        let end_cursor = first_page["pageInfo"]["endCursor"].as_str().unwrap();
+
+        let arguments = format!(r#"(first: 2, after: "{}")"#, end_cursor);
+        let data = service
+            .data(&header, user_wishlists_query(user_id, &arguments))
+            .await;
+        let second_page = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(second_page), ids[2..]);
+        assert_eq!(second_page["pageInfo"]["hasNextPage"], false);
+        assert_eq!(second_page["pageInfo"]["hasPreviousPage"], true);
+        let start_cursor = second_page["pageInfo"]["startCursor"].as_str().unwrap();
+
+        let arguments = format!(r#"(last: 1, before: "{}")"#, start_cursor);
+        let data = service
+            .data(&header, user_wishlists_query(user_id, &arguments))
+            .await;
+        let previous_page = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(previous_page), ids[1..2]);
+        assert_eq!(previous_page["pageInfo"]["hasPreviousPage"], true);
+
+        let data = service
+            .data(&header, user_wishlists_query(user_id, "(last: 2)"))
+            .await;
+        let last_page = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(last_page), ids[1..]);
+    }
+
+    #[tokio::test]
+    async fn prunes_deleted_product_variant_and_acknowledges_redelivery() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        let topics = TopicSettings::default();
+        service.add_user(user_id).await;
+        let status = service
+            .post_event("1", &topics.product_variant_created, product_variant_id)
+            .await;
+        assert_eq!(status, "SUCCESS");
+        let id = service
+            .create_wishlist(user_id, "Birthday", &[product_variant_id])
+            .await;
+
+        let status = service
+            .post_event("2", &topics.product_variant_deleted, product_variant_id)
+            .await;
+        assert_eq!(status, "SUCCESS");
+        // A redelivery of the creation event must not add the deleted product variant again.
+        let status = service
+            .post_event("1", &topics.product_variant_created, product_variant_id)
+            .await;
+        assert_eq!(status, "SUCCESS");
+
+        let wishlist = WishlistRepository::find(&service.repository, id)
+            .await
+            .unwrap();
+        assert!(wishlist.internal_product_variants.is_empty());
+        assert_eq!(wishlist.version, 1);
+        let outbox = service.repository.outbox();
+        let update_event = outbox.last().unwrap();
+        assert_eq!(update_event.topic, topics.wishlist_updated);
+        assert_eq!(
+            update_event.data.removed_product_variant_ids,
+            vec![product_variant_id]
+        );
+        let product_variant_ids = HashSet::from([product_variant_id]);
+        let product_variants = service
+            .repository
+            .find_many(&product_variant_ids)
+            .await
+            .unwrap();
+        assert!(product_variants.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_and_records_event_of_unknown_topic() {
+        let service = TestService::start().await;
+
+        let status = service.post_event("1", "unknown/topic", Uuid::new()).await;
+
+        assert_eq!(status, "DROP");
+        let failed_events = service.repository.failed_events();
+        assert_eq!(failed_events.len(), 1);
+        assert_eq!(failed_events[0].topic.as_deref(), Some("unknown/topic"));
+        assert!(!service.repository.is_processed("1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn removes_wishlists_of_deleted_user() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let topics = TopicSettings::default();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+
+        let status = service.post_event("1", &topics.user_deleted, user_id).await;
+
+        assert_eq!(status, "SUCCESS");
+        assert!(WishlistRepository::find(&service.repository, id)
+            .await
+            .is_err());
+        assert!(UserRepository::find(&service.repository, user_id)
+            .await
+            .is_err());
+        let outbox = service.repository.outbox();
+        let deletion_event = outbox.last().unwrap();
+        assert_eq!(deletion_event.topic, topics.wishlist_deleted);
+        assert_eq!(deletion_event.data.id, id);
+    }
+}
@@ -0,0 +1,432 @@
+use std::fmt;
+
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::{error::ErrorKind, options::IndexOptions, Database, IndexModel};
+
+use crate::{
+    order_datatypes::{OrderDirection, WishlistOrderField, WishlistOrderInput},
+    settings::CollectionSettings,
+};
+
+/// Name of the index MongoDB creates on `_id` of every collection.
+const ID_INDEX_NAME: &str = "_id_";
+/// Code of the MongoDB error `NamespaceExists`.
+const NAMESPACE_EXISTS_CODE: i32 = 48;
+
+/// Index the service declares on a collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDeclaration {
+    /// Name of the collection.
+    pub collection: String,
+    /// Keys of the index, in the order of the index.
+    pub keys: Document,
+    /// Whether the index enforces unique keys.
+    pub unique: bool,
+    /// Filter of the documents the index covers, `None` if the index covers all documents.
+    pub partial_filter: Option<Document>,
+}
+
+/// Deviation of the indexes of a collection from the declared indexes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexDrift {
+    /// A declared index does not exist.
+    Missing(IndexDeclaration),
+    /// An index with the keys of a declared index exists, but differs in uniqueness.
+    UniquenessDiffers {
+        declaration: IndexDeclaration,
+        name: String,
+    },
+    /// An index exists that is not declared, e.g. a left over of an earlier version.
+    Undeclared {
+        collection: String,
+        name: String,
+        keys: Document,
+    },
+}
+
+impl fmt::Display for IndexDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(declaration) => write!(
+                f,
+                "Index: `{}` of collection: `{}` is missing.",
+                declaration.keys, declaration.collection
+            ),
+            Self::UniquenessDiffers { declaration, name } => write!(
+                f,
+                "Index: `{}` of collection: `{}` is declared with unique: `{}`, but exists as `{}` with unique: `{}`.",
+                declaration.keys,
+                declaration.collection,
+                declaration.unique,
+                name,
+                !declaration.unique
+            ),
+            Self::Undeclared {
+                collection,
+                name,
+                keys,
+            } => write!(
+                f,
+                "Index: `{}` of collection: `{}` with keys: `{}` is not declared.",
+                name, collection, keys
+            ),
+        }
+    }
+}
+
+/// Indexes the service requires.
+///
+/// Wishlists of a user are filtered by `user._id` and sorted by any `WishlistOrderField` with `_id` as tiebreaker,
+/// one compound index per field serves both order directions.
+/// Shared wishlists are looked up by their share token, which is unique among the wishlists that have one.
+/// Pending outbox entries are grouped by wishlist and ordered by their sequence when claiming the next entry.
+/// The `_id` indexes of users and product variants, which MongoDB creates implicitly, back the lookups of foreign entities.
+pub fn declared_indexes(collections: &CollectionSettings) -> Vec<IndexDeclaration> {
+    let mut declarations: Vec<IndexDeclaration> = Vec::new();
+    for field in WishlistOrderField::ALL {
+        let order = WishlistOrderInput {
+            direction: Some(OrderDirection::Asc),
+            field: Some(field),
+        };
+        let mut keys = doc! {"user._id": 1};
+        for (key, direction) in order.sort_doc() {
+            if !keys.contains_key(&key) {
+                keys.insert(key, direction);
+            }
+        }
+        if declarations
+            .iter()
+            .all(|declaration| declaration.keys != keys)
+        {
+            declarations.push(IndexDeclaration {
+                collection: collections.wishlists.clone(),
+                keys,
+                unique: false,
+                partial_filter: None,
+            });
+        }
+    }
+    declarations.push(IndexDeclaration {
+        collection: collections.wishlists.clone(),
+        keys: doc! {"share_token": 1},
+        unique: true,
+        partial_filter: Some(doc! {"share_token": {"$type": "string"}}),
+    });
+    declarations.push(IndexDeclaration {
+        collection: collections.outbox.clone(),
+        keys: doc! {"delivered_at": 1, "failed_at": 1, "data.id": 1, "sequence": 1},
+        unique: false,
+        partial_filter: None,
+    });
+    for collection in [&collections.users, &collections.product_variants] {
+        declarations.push(IndexDeclaration {
+            collection: collection.clone(),
+            keys: doc! {"_id": 1},
+            unique: true,
+            partial_filter: None,
+        });
+    }
+    declarations
+}
+
+/// Compares the existing indexes of a collection with the declared indexes of the collection.
+///
+/// The implicit `_id` index is not reported as undeclared.
+///
+/// * `collection` - Name of the collection.
+/// * `declarations` - Declared indexes of the collection.
+/// * `existing_indexes` - Indexes of the collection as listed by MongoDB.
+pub fn index_drift(
+    collection: &str,
+    declarations: &[IndexDeclaration],
+    existing_indexes: &[IndexModel],
+) -> Vec<IndexDrift> {
+    let mut drift = Vec::new();
+    for declaration in declarations {
+        match existing_indexes
+            .iter()
+            .find(|index| index.keys == declaration.keys)
+        {
+            None => drift.push(IndexDrift::Missing(declaration.clone())),
+            Some(index) if is_unique(index) != declaration.unique => {
+                drift.push(IndexDrift::UniquenessDiffers {
+                    declaration: declaration.clone(),
+                    name: index_name(index),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for index in existing_indexes {
+        let is_declared = declarations
+            .iter()
+            .any(|declaration| declaration.keys == index.keys);
+        let name = index_name(index);
+        if !is_declared && name != ID_INDEX_NAME {
+            drift.push(IndexDrift::Undeclared {
+                collection: collection.to_string(),
+                name,
+                keys: index.keys.clone(),
+            });
+        }
+    }
+    drift
+}
+
+/// Checks the indexes of all collections with declared indexes against the declarations.
+///
+/// Returns the drift of all collections, missing collections are treated as collections without indexes.
+pub async fn check_indexes(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<Vec<IndexDrift>> {
+    let declarations = declared_indexes(collections);
+    let mut collection_names: Vec<&str> = declarations
+        .iter()
+        .map(|declaration| declaration.collection.as_str())
+        .collect();
+    collection_names.dedup();
+    let existing_collection_names = db_client.list_collection_names(None).await?;
+    let mut drift = Vec::new();
+    for collection_name in collection_names {
+        let existing_indexes: Vec<IndexModel> = match existing_collection_names
+            .iter()
+            .any(|name| name == collection_name)
+        {
+            true => {
+                db_client
+                    .collection::<Document>(collection_name)
+                    .list_indexes(None)
+                    .await?
+                    .try_collect()
+                    .await?
+            }
+            false => Vec::new(),
+        };
+        let collection_declarations: Vec<IndexDeclaration> = declarations
+            .iter()
+            .filter(|declaration| declaration.collection == collection_name)
+            .cloned()
+            .collect();
+        drift.extend(index_drift(
+            collection_name,
+            &collection_declarations,
+            &existing_indexes,
+        ));
+    }
+    Ok(drift)
+}
+
+/// Creates the missing declared indexes and logs any other drift of the indexes.
+///
+/// Indexes that differ from their declaration or are not declared are only logged, as dropping or rebuilding them
+/// on a large collection needs to be planned.
+/// Returns the drift that remains after the missing indexes were created.
+pub async fn ensure_indexes(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<Vec<IndexDrift>> {
+    let drift = check_indexes(db_client, collections).await?;
+    let mut remaining_drift = Vec::new();
+    for index_drift in drift {
+        match index_drift {
+            // The `_id` index exists as soon as the collection does, MongoDB rejects creating it as unique.
+            IndexDrift::Missing(declaration) if declaration.keys == doc! {"_id": 1} => {
+                match db_client
+                    .create_collection(&declaration.collection, None)
+                    .await
+                {
+                    Ok(()) => info!(
+                        "Created collection: `{}` with its `_id` index.",
+                        declaration.collection
+                    ),
+                    // Another replica created the collection since the indexes were checked.
+                    Err(error) if is_namespace_exists(&error) => (),
+                    Err(error) => return Err(error),
+                }
+            }
+            IndexDrift::Missing(declaration) => {
+                let options = IndexOptions::builder()
+                    .unique(declaration.unique)
+                    .partial_filter_expression(declaration.partial_filter.clone())
+                    .build();
+                let index = IndexModel::builder()
+                    .keys(declaration.keys.clone())
+                    .options(options)
+                    .build();
+                let result = db_client
+                    .collection::<Document>(&declaration.collection)
+                    .create_index(index, None)
+                    .await?;
+                info!(
+                    "Created index: `{}` of collection: `{}`.",
+                    result.index_name, declaration.collection
+                );
+            }
+            _ => {
+                warn!("{}", index_drift);
+                remaining_drift.push(index_drift);
+            }
+        }
+    }
+    Ok(remaining_drift)
+}
+
+/// Whether an error reports that a collection already exists.
+fn is_namespace_exists(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Command(command_error) if command_error.code == NAMESPACE_EXISTS_CODE
+    )
+}
+
+/// Whether an existing index enforces unique keys, the `_id` index always does.
+fn is_unique(index: &IndexModel) -> bool {
+    index.keys == doc! {"_id": 1}
+        || index
+            .options
+            .as_ref()
+            .and_then(|options| options.unique)
+            .unwrap_or(false)
+}
+
+/// Name of an existing index.
+fn index_name(index: &IndexModel) -> String {
+    index
+        .options
+        .as_ref()
+        .and_then(|options| options.name.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index as listed by MongoDB.
+    fn existing_index(name: &str, keys: Document, unique: Option<bool>) -> IndexModel {
+        let options = IndexOptions::builder()
+            .name(name.to_string())
+            .unique(unique)
+            .build();
+        IndexModel::builder().keys(keys).options(options).build()
+    }
+
+    #[test]
+    fn declares_compound_index_per_sortable_field() {
+        let collections = CollectionSettings::default();
+
+        let declarations = declared_indexes(&collections);
+
+        let wishlist_keys: Vec<Document> = declarations
+            .iter()
+            .filter(|declaration| declaration.collection == collections.wishlists)
+            .map(|declaration| declaration.keys.clone())
+            .collect();
+        assert_eq!(
+            wishlist_keys,
+            vec![
+                doc! {"user._id": 1, "_id": 1},
+                doc! {"user._id": 1, "name": 1, "_id": 1},
+                doc! {"user._id": 1, "created_at": 1, "_id": 1},
+                doc! {"user._id": 1, "last_updated_at": 1, "_id": 1},
+                doc! {"share_token": 1},
+            ]
+        );
+        assert!(declarations
+            .iter()
+            .any(|declaration| declaration.collection == collections.outbox
+                && declaration.keys.get_i32("sequence").is_ok()));
+        let unique_collections: Vec<&str> = declarations
+            .iter()
+            .filter(|declaration| declaration.unique)
+            .map(|declaration| declaration.collection.as_str())
+            .collect();
+        assert_eq!(
+            unique_collections,
+            vec![
+                collections.wishlists.as_str(),
+                collections.users.as_str(),
+                collections.product_variants.as_str()
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_differing_and_undeclared_indexes() {
+        let declarations = vec![
+            IndexDeclaration {
+                collection: "wishlists".to_string(),
+                keys: doc! {"user._id": 1, "_id": 1},
+                unique: false,
+                partial_filter: None,
+            },
+            IndexDeclaration {
+                collection: "wishlists".to_string(),
+                keys: doc! {"user._id": 1, "name": 1, "_id": 1},
+                unique: false,
+                partial_filter: None,
+            },
+        ];
+        let existing_indexes = vec![
+            existing_index(ID_INDEX_NAME, doc! {"_id": 1}, None),
+            existing_index(
+                "user_name",
+                doc! {"user._id": 1, "name": 1, "_id": 1},
+                Some(true),
+            ),
+            existing_index("name", doc! {"name": 1}, None),
+        ];
+
+        let drift = index_drift("wishlists", &declarations, &existing_indexes);
+
+        assert_eq!(
+            drift,
+            vec![
+                IndexDrift::Missing(declarations[0].clone()),
+                IndexDrift::UniquenessDiffers {
+                    declaration: declarations[1].clone(),
+                    name: "user_name".to_string(),
+                },
+                IndexDrift::Undeclared {
+                    collection: "wishlists".to_string(),
+                    name: "name".to_string(),
+                    keys: doc! {"name": 1},
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_namespace_exists_error() {
+        let command_error = |code: i32| -> mongodb::error::Error {
+            let command_error = bson::from_document(doc! {
+                "code": code,
+                "codeName": "NamespaceExists",
+                "errmsg": "Collection already exists.",
+            })
+            .unwrap();
+            ErrorKind::Command(command_error).into()
+        };
+
+        assert!(is_namespace_exists(&command_error(NAMESPACE_EXISTS_CODE)));
+        assert!(!is_namespace_exists(&command_error(13)));
+    }
+
+    #[test]
+    fn accepts_implicit_id_index_as_unique() {
+        let declarations = vec![IndexDeclaration {
+            collection: "users".to_string(),
+            keys: doc! {"_id": 1},
+            unique: true,
+            partial_filter: None,
+        }];
+        let existing_indexes = vec![existing_index(ID_INDEX_NAME, doc! {"_id": 1}, None)];
+
+        let drift = index_drift("users", &declarations, &existing_indexes);
+
+        assert!(drift.is_empty());
+    }
+}
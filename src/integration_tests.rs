@@ -0,0 +1,1074 @@
+use std::sync::Arc;
+
+use async_graphql::{futures_util::Stream, Request, Response, Schema};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    routing::post,
+    Router, Server,
+};
+use bson::Uuid;
+use jsonwebtoken::{EncodingKey, Header};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use crate::{
+    authentication::{Authenticator, AuthorizedUserHeader},
+    build_service_router,
+    event_publisher::EventPublisher,
+    in_memory_repository::InMemoryRepository,
+    metrics::Metrics,
+    mutation::Mutation,
+    query::Query,
+    repository::Repositories,
+    settings::{AuthenticationMode, Settings},
+    subscription::Subscription,
+    wishlist_change_broker::WishlistChangeBroker,
+};
+
+/// Events published to the Dapr sidecar stand-in, as topic and body.
+type PublishedEvents = Arc<Mutex<Vec<(String, Value)>>>;
+
+/// Service started in-process on in-memory repositories, with a stand-in of the Dapr sidecar.
+///
+/// Serves the same Router as `start_service`, apart from the health and metrics endpoints, which require MongoDB.
+pub struct TestService {
+    /// Storage of the service, can be inspected and populated directly.
+    pub repository: InMemoryRepository,
+    /// Events the service published to the Dapr sidecar.
+    pub published_events: PublishedEvents,
+    wishlist_change_broker: WishlistChangeBroker,
+    settings: Settings,
+    address: String,
+    client: reqwest::Client,
+}
+
+impl TestService {
+    /// Starts the service with the default settings.
+    pub async fn start() -> Self {
+        Self::start_with(Settings::default()).await
+    }
+
+    /// Starts the service on a free port, `settings.dapr.http_endpoint` is replaced by the Dapr sidecar stand-in.
+    pub async fn start_with(mut settings: Settings) -> Self {
+        let published_events = PublishedEvents::default();
+        let sidecar_router = Router::new()
+            .route(
+                "/v1.0/publish/:pubsub/*topic",
+                post(
+                    |State(published_events): State<PublishedEvents>,
+                     Path((_, topic)): Path<(String, String)>,
+                     body: Bytes| async move {
+                        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                        published_events.lock().await.push((topic, body));
+                    },
+                ),
+            )
+            .with_state(published_events.clone());
+        settings.dapr.http_endpoint = Some(format!("http://{}", serve(sidecar_router)));
+
+        let repository = InMemoryRepository::new(settings.topics.clone());
+        let wishlist_change_broker = WishlistChangeBroker::default();
+        let router = build_service_router(
+            Repositories::new(repository.clone()),
+            wishlist_change_broker.clone(),
+            EventPublisher::from_settings(&settings.dapr),
+            Metrics::new(),
+            None,
+            Authenticator::from_settings(&settings.authentication)
+                .await
+                .unwrap(),
+            &settings,
+        );
+        Self {
+            repository,
+            published_events,
+            wishlist_change_broker,
+            settings,
+            address: format!("http://{}", serve(router)),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Sends a GraphQL request, returns the response with `data` and `errors`.
+    ///
+    /// * `authorized_user` - Value of the `Authorized-User` header, the header is not set if `None`.
+    /// * `query` - GraphQL query.
+    pub async fn execute(&self, authorized_user: Option<&str>, query: impl Into<String>) -> Value {
+        let headers: Vec<(&str, String)> = authorized_user
+            .map(|definitely_authorized_user| {
+                ("Authorized-User", definitely_authorized_user.to_string())
+            })
+            .into_iter()
+            .collect();
+        self.execute_with_headers(&headers, query).await
+    }
+
+    /// Sends a GraphQL request with a JWT in the `Authorization: Bearer` header, returns the response.
+    pub async fn execute_with_token(&self, token: &str, query: impl Into<String>) -> Value {
+        let headers = [("Authorization", format!("Bearer {}", token))];
+        self.execute_with_headers(&headers, query).await
+    }
+
+    /// Sends a GraphQL request with headers, returns the response with `data` and `errors`.
+    pub async fn execute_with_headers(
+        &self,
+        headers: &[(&str, String)],
+        query: impl Into<String>,
+    ) -> Value {
+        let mut request = self
+            .client
+            .post(&self.address)
+            .json(&json!({ "query": query.into() }));
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        request.send().await.unwrap().json().await.unwrap()
+    }
+
+    /// Sends a GraphQL request as a user, returns the data and panics on errors.
+    pub async fn data(&self, authorized_user: &str, query: impl Into<String>) -> Value {
+        let response = self.execute(Some(authorized_user), query).await;
+        assert!(response["errors"].is_null(), "{}", response["errors"]);
+        response["data"].clone()
+    }
+
+    /// Starts a GraphQL subscription as a user, returns the stream of its responses.
+    ///
+    /// Runs on a schema sharing the storage and the subscription broker of the service, instead of a WebSocket connection.
+    /// The subscription receives changes once the stream has been polled.
+    pub fn subscribe(
+        &self,
+        authorized_user: &str,
+        query: impl Into<String>,
+    ) -> impl Stream<Item = Response> + Unpin {
+        let schema = Schema::build(Query, Mutation, Subscription)
+            .data(Repositories::new(self.repository.clone()))
+            .data(self.wishlist_change_broker.clone())
+            .data(self.settings.clone())
+            .enable_federation()
+            .finish();
+        let authenticate_user_header: AuthorizedUserHeader =
+            serde_json::from_str(authorized_user).unwrap();
+        schema.execute_stream(Request::new(query).data(authenticate_user_header))
+    }
+
+    /// Posts a CloudEvent to `/on-topic-event`, returns the Dapr status of the response.
+    pub async fn post_event(&self, event_id: &str, topic: &str, id: Uuid) -> String {
+        let event = json!({ "id": event_id, "topic": topic, "data": { "id": id } });
+        self.post_event_body(event.to_string()).await
+    }
+
+    /// Posts a raw event payload to `/on-topic-event`, returns the Dapr status of the response.
+    pub async fn post_event_body(&self, body: impl Into<reqwest::Body>) -> String {
+        let response: Value = self
+            .client
+            .post(format!("{}/on-topic-event", self.address))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["status"].as_str().unwrap().to_string()
+    }
+
+    /// Sends a GET request to a path of the service, returns the JSON response.
+    pub async fn get(&self, path: &str) -> Value {
+        let url = format!("{}{}", self.address, path);
+        self.client
+            .get(url)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// Makes a user known to the service with a user creation event.
+    pub async fn add_user(&self, id: Uuid) {
+        let topic = Settings::default().topics.user_created;
+        let status = self.post_event(&Uuid::new().to_string(), &topic, id).await;
+        assert_eq!(status, "SUCCESS");
+    }
+
+    /// Makes a product variant known to the service with a product variant creation event.
+    pub async fn add_product_variant(&self, id: Uuid) {
+        let topic = Settings::default().topics.product_variant_created;
+        let status = self.post_event(&Uuid::new().to_string(), &topic, id).await;
+        assert_eq!(status, "SUCCESS");
+    }
+
+    /// Creates a wishlist of a user as the user, returns its UUID.
+    pub async fn create_wishlist(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        product_variant_ids: &[Uuid],
+    ) -> Uuid {
+        let query = format!(
+            r#"mutation {{ createWishlist(input: {{ userId: "{}", productVariantIds: {}, name: "{}" }}) {{ id }} }}"#,
+            user_id,
+            json!(product_variant_ids),
+            name
+        );
+        let data = self.data(&buyer(user_id), query).await;
+        Uuid::parse_str(data["createWishlist"]["id"].as_str().unwrap()).unwrap()
+    }
+
+    /// Sets the visibility of a wishlist as its owner.
+    pub async fn set_visibility(&self, user_id: Uuid, id: Uuid, visibility: &str) {
+        let query = format!(
+            r#"mutation {{ updateWishlist(input: {{ id: "{}", visibility: {} }}) {{ id }} }}"#,
+            id, visibility
+        );
+        self.data(&buyer(user_id), query).await;
+    }
+
+    /// Makes a user an accepted member of a wishlist, invited by its owner.
+    ///
+    /// * `role` - GraphQL name of the role, `VIEWER` or `EDITOR`.
+    pub async fn add_member(&self, owner_id: Uuid, id: Uuid, user_id: Uuid, role: &str) {
+        self.add_user(user_id).await;
+        let invitation = format!(
+            r#"mutation {{ inviteWishlistMember(input: {{ wishlistId: "{}", userId: "{}", role: {} }}) {{ id }} }}"#,
+            id, user_id, role
+        );
+        self.data(&buyer(owner_id), invitation).await;
+        let acceptance = format!(
+            r#"mutation {{ acceptWishlistInvitation(wishlistId: "{}") {{ id }} }}"#,
+            id
+        );
+        self.data(&buyer(user_id), acceptance).await;
+    }
+}
+
+/// Serves a Router on a free local port, returns its address.
+pub fn serve(router: Router) -> String {
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+    let address = server.local_addr().to_string();
+    tokio::spawn(server);
+    address
+}
+
+/// `Authorized-User` header of a user with roles.
+pub fn authorized_user(id: Uuid, roles: &[&str]) -> String {
+    json!({ "id": id, "roles": roles }).to_string()
+}
+
+/// `Authorized-User` header of a buyer.
+pub fn buyer(id: Uuid) -> String {
+    authorized_user(id, &["buyer"])
+}
+
+/// Secret of the HMAC signed JWTs of the tests.
+pub const JWT_SECRET: &str = "integration-test-secret";
+
+/// Settings of `jwt` mode with HMAC signed JWTs of audience `wishlist` and issuer `https://auth.misarch.test`.
+pub fn jwt_settings() -> Settings {
+    let mut settings = Settings::default();
+    settings.authentication.mode = AuthenticationMode::Jwt;
+    settings.authentication.hmac_secret = Some(JWT_SECRET.to_string());
+    settings.authentication.audience = Some("wishlist".to_string());
+    settings.authentication.issuer = Some("https://auth.misarch.test".to_string());
+    settings
+}
+
+/// Claims of a JWT of a user with roles, accepted by `jwt_settings` for an hour.
+pub fn jwt_claims(id: Uuid, roles: &[&str]) -> Value {
+    json!({
+        "sub": id,
+        "roles": roles,
+        "aud": "wishlist",
+        "iss": "https://auth.misarch.test",
+        "exp": jsonwebtoken::get_current_timestamp() + 3600,
+    })
+}
+
+/// JWT of claims signed with HS256.
+pub fn sign_jwt(claims: &Value, secret: &str) -> String {
+    let key = EncodingKey::from_secret(secret.as_bytes());
+    jsonwebtoken::encode(&Header::default(), claims, &key).unwrap()
+}
+
+/// Messages of the errors of a GraphQL response.
+pub fn error_messages(response: &Value) -> Vec<String> {
+    response["errors"]
+        .as_array()
+        .map(|errors| {
+            errors
+                .iter()
+                .map(|error| error["message"].as_str().unwrap_or_default().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// UUIDs of the nodes of a connection.
+pub fn node_ids(connection: &Value) -> Vec<String> {
+    connection["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Query of a page of the wishlists of a user.
+pub fn user_wishlists_query(user_id: Uuid, arguments: &str) -> String {
+    format!(
+        r#"{{ _entities(representations: [{{ __typename: "User", id: "{}" }}]) {{ ... on User {{ wishlists{} {{ nodes {{ id name }} hasNextPage totalCount pageInfo {{ hasNextPage hasPreviousPage startCursor endCursor }} }} }} }} }}"#,
+        user_id, arguments
+    )
+}
+
+/// Query of a wishlist.
+fn wishlist_query(id: Uuid) -> String {
+    format!(r#"{{ wishlist(id: "{}") {{ id name }} }}"#, id)
+}
+
+mod authorization {
+    use super::*;
+
+    #[tokio::test]
+    async fn permits_owner_to_read_wishlist() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+
+        let data = service.data(&buyer(user_id), wishlist_query(id)).await;
+
+        assert_eq!(data["wishlist"]["name"], "Birthday");
+    }
+
+    #[tokio::test]
+    async fn denies_other_buyer_to_read_private_wishlist() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let other_user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+
+        let response = service
+            .execute(Some(&buyer(other_user_id)), wishlist_query(id))
+            .await;
+
+        let expected_message = format!(
+            "Authentication failed for user of UUID: `{}`. Operation on wishlist of id: `{}` not permitted.",
+            other_user_id, id
+        );
+        assert_eq!(error_messages(&response), vec![expected_message]);
+    }
+
+    #[tokio::test]
+    async fn permits_permissive_roles_regardless_of_user() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+
+        for role in ["admin", "employee"] {
+            let header = authorized_user(Uuid::new(), &[role]);
+            let data = service.data(&header, wishlist_query(id)).await;
+            assert_eq!(data["wishlist"]["name"], "Birthday");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_and_malformed_header() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+        let expected_message =
+            "Authentication failed. Authorized-User header is not set or could not be parsed.";
+
+        for header in [None, Some("not json"), Some(r#"{"id": "1", "roles": []}"#)] {
+            let response = service.execute(header, wishlist_query(id)).await;
+            assert_eq!(error_messages(&response), vec![expected_message]);
+        }
+    }
+
+    #[tokio::test]
+    async fn denies_buyer_to_create_wishlist_of_other_user() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let other_user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let query = format!(
+            r#"mutation {{ createWishlist(input: {{ userId: "{}", productVariantIds: [], name: "Birthday" }}) {{ id }} }}"#,
+            user_id
+        );
+
+        let response = service.execute(Some(&buyer(other_user_id)), query).await;
+
+        let expected_message = format!(
+            "Authentication failed for user of UUID: `{}`. Operation not permitted.",
+            other_user_id
+        );
+        assert_eq!(error_messages(&response), vec![expected_message]);
+        assert!(service.repository.outbox().is_empty());
+    }
+
+    #[tokio::test]
+    async fn permits_anyone_to_read_public_wishlist() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+        let query = format!(
+            r#"mutation {{ updateWishlist(input: {{ id: "{}", visibility: PUBLIC }}) {{ id }} }}"#,
+            id
+        );
+        service.data(&buyer(user_id), query).await;
+
+        let anonymous_response = service.execute(None, wishlist_query(id)).await;
+        let other_buyer_data = service.data(&buyer(Uuid::new()), wishlist_query(id)).await;
+
+        assert_eq!(anonymous_response["data"]["wishlist"]["name"], "Birthday");
+        assert_eq!(other_buyer_data["wishlist"]["name"], "Birthday");
+    }
+
+    #[tokio::test]
+    async fn shows_members_only_to_editors_and_owner() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let viewer_id = Uuid::new();
+        let editor_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+        service.set_visibility(user_id, id, "PUBLIC").await;
+        service.add_member(user_id, id, viewer_id, "VIEWER").await;
+        service.add_member(user_id, id, editor_id, "EDITOR").await;
+        let query = format!(r#"{{ wishlist(id: "{}") {{ members {{ role }} }} }}"#, id);
+
+        let member_counts = [
+            service.execute(None, query.clone()).await["data"].clone(),
+            service.data(&buyer(Uuid::new()), query.clone()).await,
+            service.data(&buyer(viewer_id), query.clone()).await,
+            service.data(&buyer(editor_id), query.clone()).await,
+            service.data(&buyer(user_id), query.clone()).await,
+        ]
+        .map(|data| data["wishlist"]["members"].as_array().unwrap().len());
+
+        assert_eq!(member_counts, [0, 0, 0, 2, 2]);
+    }
+}
+
+mod user_wishlists {
+    use super::*;
+
+    /// Starts the service with a user owning three wishlists, returns the user and the sorted wishlist UUIDs.
+    async fn start_with_wishlists() -> (TestService, Uuid, Vec<String>) {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let mut ids = Vec::new();
+        for name in ["c", "a", "b"] {
+            ids.push(service.create_wishlist(user_id, name, &[]).await);
+        }
+        ids.sort_by_key(|id| id.bytes());
+        (service, user_id, ids.iter().map(Uuid::to_string).collect())
+    }
+
+    #[tokio::test]
+    async fn paginates_with_skip() {
+        let (service, user_id, ids) = start_with_wishlists().await;
+
+        let data = service
+            .data(
+                &buyer(user_id),
+                user_wishlists_query(user_id, "(first: 1, skip: 1)"),
+            )
+            .await;
+
+        let wishlists = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(wishlists), vec![ids[1].clone()]);
+        assert_eq!(wishlists["hasNextPage"], true);
+        assert_eq!(wishlists["pageInfo"]["hasPreviousPage"], true);
+        assert_eq!(wishlists["totalCount"], 3);
+    }
+
+    #[tokio::test]
+    async fn paginates_in_order_of_field() {
+        let (service, user_id, _) = start_with_wishlists().await;
+
+        let data = service
+            .data(
+                &buyer(user_id),
+                user_wishlists_query(user_id, "(orderBy: { field: NAME, direction: DESC })"),
+            )
+            .await;
+
+        let names: Vec<&str> = data["_entities"][0]["wishlists"]["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["c", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn continues_order_of_field_after_cursor() {
+        let (service, user_id, _) = start_with_wishlists().await;
+        let header = buyer(user_id);
+        let first_page = service
+            .data(
+                &header,
+                user_wishlists_query(user_id, "(first: 2, orderBy: { field: NAME })"),
+            )
+            .await;
+        let end_cursor = first_page["_entities"][0]["wishlists"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let arguments = format!(
+            r#"(first: 2, after: "{}", orderBy: {{ field: NAME }})"#,
+            end_cursor
+        );
+        let data = service
+            .data(&header, user_wishlists_query(user_id, &arguments))
+            .await;
+
+        let wishlists = &data["_entities"][0]["wishlists"];
+        assert_eq!(wishlists["nodes"][0]["name"], "c");
+        assert_eq!(wishlists["nodes"].as_array().unwrap().len(), 1);
+        assert_eq!(wishlists["hasNextPage"], false);
+    }
+
+    #[tokio::test]
+    async fn shows_only_public_wishlists_to_other_users() {
+        let (service, user_id, ids) = start_with_wishlists().await;
+        let query = format!(
+            r#"mutation {{ updateWishlist(input: {{ id: "{}", visibility: PUBLIC }}) {{ id }} }}"#,
+            ids[2]
+        );
+        service.data(&buyer(user_id), query).await;
+
+        let data = service
+            .data(&buyer(Uuid::new()), user_wishlists_query(user_id, ""))
+            .await;
+
+        let wishlists = &data["_entities"][0]["wishlists"];
+        assert_eq!(node_ids(wishlists), vec![ids[2].clone()]);
+        assert_eq!(wishlists["totalCount"], 1);
+    }
+
+    #[tokio::test]
+    async fn includes_wishlists_of_accepted_membership() {
+        let (service, user_id, ids) = start_with_wishlists().await;
+        let member_id = Uuid::new();
+        let id = Uuid::parse_str(&ids[0]).unwrap();
+        service.add_member(user_id, id, member_id, "VIEWER").await;
+
+        let data = service
+            .data(&buyer(member_id), user_wishlists_query(user_id, ""))
+            .await;
+
+        assert_eq!(
+            node_ids(&data["_entities"][0]["wishlists"]),
+            vec![ids[0].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_anonymous_reader() {
+        let (service, user_id, _) = start_with_wishlists().await;
+
+        let response = service
+            .execute(None, user_wishlists_query(user_id, ""))
+            .await;
+
+        let expected_message =
+            "Authentication failed. Authorized-User header is not set or could not be parsed.";
+        assert_eq!(error_messages(&response), vec![expected_message]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_pagination_arguments() {
+        let (service, user_id, _) = start_with_wishlists().await;
+        let header = buyer(user_id);
+        let cases = [
+            (
+                "(first: 101)",
+                "`first`: `101` exceeds the maximum page size of `100`.",
+            ),
+            (
+                "(first: 1, last: 1)",
+                "Pagination with both `first` and `last` is not supported.",
+            ),
+            ("(after: \"invalid\")", "Cursor: `invalid` is not valid."),
+        ];
+
+        for (arguments, expected_message) in cases {
+            let response = service
+                .execute(Some(&header), user_wishlists_query(user_id, arguments))
+                .await;
+            assert_eq!(
+                error_messages(&response),
+                vec![expected_message],
+                "{}",
+                arguments
+            );
+        }
+    }
+}
+
+mod subscriptions {
+    use std::time::Duration;
+
+    use async_graphql::futures_util::{poll, StreamExt};
+
+    use super::*;
+
+    /// Subscription to the name of a wishlist.
+    fn wishlist_updated_subscription(id: Uuid) -> String {
+        format!(
+            r#"subscription {{ wishlistUpdated(id: "{}") {{ name }} }}"#,
+            id
+        )
+    }
+
+    /// Receives the next response of a subscription, `None` if the subscription ended.
+    async fn next_response(
+        stream: &mut (impl Stream<Item = Response> + Unpin),
+    ) -> Option<Response> {
+        tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("Subscription neither yielded nor ended.")
+    }
+
+    /// Renames a wishlist as a user.
+    async fn rename_wishlist(service: &TestService, user_id: Uuid, id: Uuid, name: &str) {
+        let query = format!(
+            r#"mutation {{ updateWishlist(input: {{ id: "{}", name: "{}" }}) {{ id }} }}"#,
+            id, name
+        );
+        service.data(&buyer(user_id), query).await;
+    }
+
+    #[tokio::test]
+    async fn ends_wishlist_updated_when_wishlist_becomes_private() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+        service.set_visibility(user_id, id, "PUBLIC").await;
+        let mut stream = service.subscribe(&buyer(Uuid::new()), wishlist_updated_subscription(id));
+        assert!(poll!(stream.next()).is_pending());
+
+        rename_wishlist(&service, user_id, id, "Wedding").await;
+        service.set_visibility(user_id, id, "PRIVATE").await;
+
+        let response = next_response(&mut stream).await.unwrap();
+        assert_eq!(
+            response.data.into_json().unwrap()["wishlistUpdated"]["name"],
+            "Wedding"
+        );
+        assert!(next_response(&mut stream).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn ends_wishlist_updated_when_member_is_removed() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let member_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+        service.add_member(user_id, id, member_id, "VIEWER").await;
+        let mut stream = service.subscribe(&buyer(member_id), wishlist_updated_subscription(id));
+        assert!(poll!(stream.next()).is_pending());
+
+        rename_wishlist(&service, user_id, id, "Wedding").await;
+        let removal = format!(
+            r#"mutation {{ removeWishlistMember(wishlistId: "{}", userId: "{}") {{ id }} }}"#,
+            id, member_id
+        );
+        service.data(&buyer(user_id), removal).await;
+        rename_wishlist(&service, user_id, id, "Anniversary").await;
+
+        let response = next_response(&mut stream).await.unwrap();
+        assert_eq!(
+            response.data.into_json().unwrap()["wishlistUpdated"]["name"],
+            "Wedding"
+        );
+        assert!(next_response(&mut stream).await.is_none());
+    }
+}
+
+mod wishlist_product_variants {
+    use super::*;
+
+    #[tokio::test]
+    async fn paginates_product_variants_in_order_of_id() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let mut product_variant_ids = vec![Uuid::new(), Uuid::new(), Uuid::new()];
+        for id in &product_variant_ids {
+            service.add_product_variant(*id).await;
+        }
+        let id = service
+            .create_wishlist(user_id, "Birthday", &product_variant_ids)
+            .await;
+        product_variant_ids.sort_by_key(|id| id.bytes());
+        let query = |arguments: &str| {
+            format!(
+                r#"{{ wishlist(id: "{}") {{ productVariants{} {{ nodes {{ id }} hasNextPage totalCount }} }} }}"#,
+                id, arguments
+            )
+        };
+        let header = buyer(user_id);
+
+        let first_page = service.data(&header, query("(first: 2)")).await;
+        let second_page = service.data(&header, query("(first: 2, skip: 2)")).await;
+        let descending = service
+            .data(&header, query("(orderBy: { direction: DESC })"))
+            .await;
+
+        let first_page = &first_page["wishlist"]["productVariants"];
+        assert_eq!(
+            node_ids(first_page),
+            vec![
+                product_variant_ids[0].to_string(),
+                product_variant_ids[1].to_string()
+            ]
+        );
+        assert_eq!(first_page["hasNextPage"], true);
+        assert_eq!(first_page["totalCount"], 3);
+        let second_page = &second_page["wishlist"]["productVariants"];
+        assert_eq!(
+            node_ids(second_page),
+            vec![product_variant_ids[2].to_string()]
+        );
+        assert_eq!(second_page["hasNextPage"], false);
+        let descending = &descending["wishlist"]["productVariants"];
+        assert_eq!(
+            node_ids(descending),
+            product_variant_ids
+                .iter()
+                .rev()
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn clears_note_of_item_set_to_null() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let product_variant_id = Uuid::new();
+        service.add_product_variant(product_variant_id).await;
+        let id = service
+            .create_wishlist(user_id, "Birthday", &[product_variant_id])
+            .await;
+        let update = |note: &str| {
+            format!(
+                r#"mutation {{ updateWishlistItem(input: {{ wishlistId: "{}", productVariantId: "{}", note: {} }}) {{ items {{ nodes {{ note }} }} }} }}"#,
+                id, product_variant_id, note
+            )
+        };
+        let header = buyer(user_id);
+
+        let noted = service.data(&header, update(r#""Red""#)).await;
+        let cleared = service.data(&header, update("null")).await;
+
+        assert_eq!(
+            noted["updateWishlistItem"]["items"]["nodes"][0]["note"],
+            "Red"
+        );
+        assert!(cleared["updateWishlistItem"]["items"]["nodes"][0]["note"].is_null());
+    }
+}
+
+mod remove_product_variants {
+    use super::*;
+    use crate::repository::ProductVariantRepository;
+
+    /// Mutation that removes product variants from a wishlist, returning the remaining product variants.
+    fn remove_query(id: Uuid, product_variant_ids: &[Uuid]) -> String {
+        format!(
+            r#"mutation {{ removeProductVariantsFromWishlist(id: "{}", productVariantIds: {}) {{ productVariants {{ nodes {{ id }} }} }} }}"#,
+            id,
+            json!(product_variant_ids)
+        )
+    }
+
+    #[tokio::test]
+    async fn removes_product_variant_unknown_to_catalog() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        service.add_user(user_id).await;
+        service.add_product_variant(product_variant_id).await;
+        let id = service
+            .create_wishlist(user_id, "Birthday", &[product_variant_id])
+            .await;
+        ProductVariantRepository::delete(&service.repository, product_variant_id)
+            .await
+            .unwrap();
+
+        let data = service
+            .data(&buyer(user_id), remove_query(id, &[product_variant_id]))
+            .await;
+
+        let product_variants = &data["removeProductVariantsFromWishlist"]["productVariants"];
+        assert!(node_ids(product_variants).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_product_variant_not_on_wishlist() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        service.add_user(user_id).await;
+        let id = service.create_wishlist(user_id, "Birthday", &[]).await;
+
+        let response = service
+            .execute(
+                Some(&buyer(user_id)),
+                remove_query(id, &[product_variant_id]),
+            )
+            .await;
+
+        let expected_message = format!(
+            "Product variant with the UUID: `{}` is not on wishlist of id: `{}`.",
+            product_variant_id, id
+        );
+        assert_eq!(error_messages(&response), vec![expected_message]);
+    }
+}
+
+mod create_wishlist_validation {
+    use super::*;
+
+    /// Creation request of a wishlist, returns the error messages.
+    async fn create_wishlist_errors(
+        service: &TestService,
+        user_id: Uuid,
+        product_variant_ids: &[Uuid],
+    ) -> Vec<String> {
+        let query = format!(
+            r#"mutation {{ createWishlist(input: {{ userId: "{}", productVariantIds: {}, name: "Birthday" }}) {{ id }} }}"#,
+            user_id,
+            json!(product_variant_ids)
+        );
+        let response = service.execute(Some(&buyer(user_id)), query).await;
+        error_messages(&response)
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_product_variant() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        service.add_user(user_id).await;
+
+        let messages = create_wishlist_errors(&service, user_id, &[product_variant_id]).await;
+
+        let expected_message = format!(
+            "Product variant with the UUID: `{}` is not present in the system.",
+            product_variant_id
+        );
+        assert_eq!(messages, vec![expected_message]);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_user() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+
+        let messages = create_wishlist_errors(&service, user_id, &[]).await;
+
+        let expected_message = format!("User with UUID: `{}` not found.", user_id);
+        assert_eq!(messages, vec![expected_message]);
+    }
+
+    #[tokio::test]
+    async fn rejects_more_items_than_limit() {
+        let mut settings = Settings::default();
+        settings.limits.max_wishlist_items = 1;
+        let service = TestService::start_with(settings).await;
+        let user_id = Uuid::new();
+        let product_variant_ids = [Uuid::new(), Uuid::new()];
+        service.add_user(user_id).await;
+        for id in product_variant_ids {
+            service.add_product_variant(id).await;
+        }
+
+        let messages = create_wishlist_errors(&service, user_id, &product_variant_ids).await;
+
+        let expected_message = "A wishlist can contain at most `1` product variants.";
+        assert_eq!(messages, vec![expected_message]);
+    }
+
+    #[tokio::test]
+    async fn accepts_known_user_and_product_variants() {
+        let service = TestService::start().await;
+        let user_id = Uuid::new();
+        let product_variant_id = Uuid::new();
+        service.add_user(user_id).await;
+        service.add_product_variant(product_variant_id).await;
+
+        let messages = create_wishlist_errors(&service, user_id, &[product_variant_id]).await;
+
+        assert!(messages.is_empty());
+    }
+}
+
+mod dapr_endpoints {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_subscribed_topics() {
+        let service = TestService::start().await;
+
+        let subscriptions = service.get("/dapr/subscribe").await;
+
+        let topics: Vec<&str> = subscriptions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|subscription| subscription["topic"].as_str().unwrap())
+            .collect();
+        assert_eq!(topics, Settings::default().topics.subscribed());
+        assert_eq!(subscriptions[0]["route"], "/on-topic-event");
+    }
+
+    #[tokio::test]
+    async fn forwards_malformed_event_to_dead_letter_topic() {
+        let service = TestService::start().await;
+
+        let status = service.post_event_body(r#"{"id": "1"}"#).await;
+
+        assert_eq!(status, "DROP");
+        let published_events = service.published_events.lock().await;
+        assert_eq!(published_events.len(), 1);
+        assert_eq!(
+            published_events[0].0,
+            Settings::default().topics.dead_letter
+        );
+        assert_eq!(published_events[0].1, json!({ "id": "1" }));
+        assert_eq!(service.repository.failed_events().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn drops_event_of_unknown_topic_without_parsing_data() {
+        let service = TestService::start().await;
+        let event =
+            json!({ "id": "1", "topic": "discount/coupon/created", "data": { "code": "SALE" } });
+
+        let status = service.post_event_body(event.to_string()).await;
+
+        assert_eq!(status, "DROP");
+        assert!(service.published_events.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwards_event_with_malformed_data_to_dead_letter_topic() {
+        let service = TestService::start().await;
+        let topic = Settings::default().topics.user_created;
+        let event = json!({ "id": "1", "topic": topic, "data": { "id": "not-a-uuid" } });
+
+        let status = service.post_event_body(event.to_string()).await;
+
+        assert_eq!(status, "DROP");
+        let published_events = service.published_events.lock().await;
+        assert_eq!(published_events.len(), 1);
+        assert_eq!(published_events[0].1, event);
+    }
+}
+
+mod jwt_authentication {
+    use super::*;
+
+    /// Starts the service in `jwt` mode with a user owning a wishlist, returns the user and the wishlist UUID.
+    async fn start_with_wishlist(settings: Settings) -> (TestService, Uuid, Uuid) {
+        let service = TestService::start_with(settings).await;
+        let user_id = Uuid::new();
+        service.add_user(user_id).await;
+        let token = sign_jwt(&jwt_claims(user_id, &["buyer"]), JWT_SECRET);
+        let query = format!(
+            r#"mutation {{ createWishlist(input: {{ userId: "{}", productVariantIds: [], name: "Birthday" }}) {{ id }} }}"#,
+            user_id
+        );
+        let response = service.execute_with_token(&token, query).await;
+        assert!(response["errors"].is_null(), "{}", response["errors"]);
+        let id = response["data"]["createWishlist"]["id"].as_str().unwrap();
+        (service, user_id, Uuid::parse_str(id).unwrap())
+    }
+
+    #[tokio::test]
+    async fn permits_user_of_valid_token() {
+        let (service, user_id, id) = start_with_wishlist(jwt_settings()).await;
+        let token = sign_jwt(&jwt_claims(user_id, &["buyer"]), JWT_SECRET);
+
+        let response = service.execute_with_token(&token, wishlist_query(id)).await;
+
+        assert_eq!(response["data"]["wishlist"]["name"], "Birthday");
+    }
+
+    #[tokio::test]
+    async fn ignores_authorized_user_header() {
+        let (service, _, id) = start_with_wishlist(jwt_settings()).await;
+        let forged_header = authorized_user(Uuid::new(), &["admin"]);
+
+        let response = service
+            .execute(Some(&forged_header), wishlist_query(id))
+            .await;
+
+        assert_eq!(
+            error_messages(&response),
+            vec!["Authentication failed. Authorization header does not contain a bearer token."]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_with_invalid_signature_or_claims() {
+        let (service, user_id, id) = start_with_wishlist(jwt_settings()).await;
+        let claims = jwt_claims(user_id, &["buyer"]);
+        let mut expired_claims = claims.clone();
+        expired_claims["exp"] = json!(jsonwebtoken::get_current_timestamp() - 3600);
+        let mut other_audience_claims = claims.clone();
+        other_audience_claims["aud"] = json!("catalog");
+        let mut other_issuer_claims = claims.clone();
+        other_issuer_claims["iss"] = json!("https://attacker.test");
+        let cases = [
+            (sign_jwt(&claims, "other-secret"), "InvalidSignature"),
+            (sign_jwt(&expired_claims, JWT_SECRET), "ExpiredSignature"),
+            (
+                sign_jwt(&other_audience_claims, JWT_SECRET),
+                "InvalidAudience",
+            ),
+            (sign_jwt(&other_issuer_claims, JWT_SECRET), "InvalidIssuer"),
+        ];
+
+        for (token, reason) in cases {
+            let response = service.execute_with_token(&token, wishlist_query(id)).await;
+            let expected_message = format!("Authentication failed. JWT is not valid: {}.", reason);
+            assert_eq!(error_messages(&response), vec![expected_message]);
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_roles_of_nested_claim() {
+        let mut settings = jwt_settings();
+        settings.authentication.roles_claim = "realm_access.roles".to_string();
+        let (service, _, id) = start_with_wishlist(settings).await;
+        let mut claims = jwt_claims(Uuid::new(), &[]);
+        claims["realm_access"] = json!({ "roles": ["offline_access", "employee"] });
+
+        let response = service
+            .execute_with_token(&sign_jwt(&claims, JWT_SECRET), wishlist_query(id))
+            .await;
+
+        assert_eq!(response["data"]["wishlist"]["name"], "Birthday");
+    }
+}
@@ -1,29 +1,31 @@
-use std::{collections::HashSet, env, fs::File, io::Write};
+use std::{fs::File, io::Write, sync::Arc};
 
 use async_graphql::{
-    extensions::Logger, http::GraphiQLSource, EmptySubscription, SDLExportOptions, Schema,
+    extensions::{Logger, OpenTelemetry},
+    http::GraphiQLSource,
+    http::ALL_WEBSOCKET_PROTOCOLS,
+    Data, SDLExportOptions, Schema,
 };
 
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 
 use axum::{
-    extract::State,
-    http::{header::HeaderMap, StatusCode},
-    response::{self, IntoResponse},
+    extract::{State, WebSocketUpgrade},
+    http::header::HeaderMap,
+    middleware,
+    response::{self, IntoResponse, Response},
     routing::{get, post},
     Router, Server,
 };
-use clap::{arg, command, Parser};
+use clap::{Parser, Subcommand};
 
-use simple_logger::SimpleLogger;
-
-use log::info;
+use log::{error, info};
 use mongodb::{bson::DateTime, options::ClientOptions, Client, Collection, Database};
 
 use bson::Uuid;
 
 mod wishlist;
-use wishlist::Wishlist;
+use wishlist::{Wishlist, WishlistVisibility};
 
 mod query;
 use query::Query;
@@ -31,7 +33,26 @@ use query::Query;
 mod mutation;
 use mutation::Mutation;
 
-use foreign_types::ProductVariant;
+mod subscription;
+use subscription::Subscription;
+
+use event_publisher::EventPublisher;
+use health::{live, ready, HealthState};
+use indexes::{check_indexes, ensure_indexes};
+use logging::{
+    configure_logging, correlate_request, current_request_context, init_logging, RequestContext,
+};
+use metrics::{export_metrics, GraphQLMetrics, Metrics, MetricsState, MongoDbMetrics};
+use migrations::{check_schema_version, migrate_up, migration_dry_run, migration_status};
+use mongodb_repository::MongoDbRepository;
+use opentelemetry::trace::FutureExt;
+use opentelemetry_sdk::trace::Tracer;
+use outbox::{drain_outbox, purge_delivered_entries, OutboxEntry, OutboxSignal};
+use repository::Repositories;
+use settings::{MongoDbSettings, Settings, SettingsArgs};
+use telemetry::{context_from_headers, init_tracing};
+use trash::purge_trashed_wishlists;
+use wishlist_change_broker::WishlistChangeBroker;
 
 mod user;
 use user::User;
@@ -40,32 +61,61 @@ mod http_event_service;
 use http_event_service::{list_topic_subscriptions, on_topic_event, HttpEventServiceState};
 
 mod authentication;
-use authentication::AuthorizedUserHeader;
+use authentication::{AuthenticationFailure, Authenticator};
 
 mod base_connection;
+mod event_publisher;
 mod foreign_types;
+mod health;
+#[cfg(test)]
+mod in_memory_repository;
+mod indexes;
+#[cfg(test)]
+mod integration_tests;
+mod logging;
+mod metrics;
+mod migrations;
+mod mongodb_repository;
 mod mutation_input_structs;
 mod order_datatypes;
+mod outbox;
 mod product_variant_connection;
+mod repository;
+mod settings;
+mod telemetry;
+mod trash;
+mod wishlist_change_broker;
 mod wishlist_connection;
+mod wishlist_item;
+mod wishlist_item_connection;
+mod wishlist_member;
 
 /// Builds the GraphiQL frontend.
 async fn graphiql() -> impl IntoResponse {
-    response::Html(GraphiQLSource::build().endpoint("/").finish())
+    response::Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
 }
 
 /// Establishes database connection and returns the client.
-async fn db_connection() -> Client {
-    let uri = match env::var_os("MONGODB_URI") {
-        Some(uri) => uri.into_string().unwrap(),
-        None => panic!("$MONGODB_URI is not set."),
+///
+/// * `settings` - MongoDB settings of the service.
+/// * `metrics` - Metrics that record the latencies of MongoDB commands.
+async fn db_connection(settings: &MongoDbSettings, metrics: &Metrics) -> Client {
+    let uri = match &settings.uri {
+        Some(uri) => uri,
+        None => panic!("MongoDB URI is not set, use `--mongodb-uri` or $MONGODB_URI."),
     };
 
     // Parse a connection string into an options struct.
     let mut client_options = ClientOptions::parse(uri).await.unwrap();
 
     // Manually set an option.
-    client_options.app_name = Some("Wishlist".to_string());
+    client_options.app_name = Some(settings.app_name.clone());
+    client_options.command_event_handler = Some(Arc::new(MongoDbMetrics(metrics.clone())));
 
     // Get a handle to the deployment.
     Client::with_options(client_options).unwrap()
@@ -74,20 +124,75 @@ async fn db_connection() -> Client {
 /// Returns Router that establishes connection to Dapr.
 ///
 /// Adds endpoints to define pub/sub interaction with Dapr.
-async fn build_dapr_router(db_client: Database) -> Router {
-    let product_variant_collection: mongodb::Collection<ProductVariant> =
-        db_client.collection::<ProductVariant>("product_variants");
-    let user_collection: mongodb::Collection<User> = db_client.collection::<User>("users");
-
+fn build_dapr_router(
+    repositories: Repositories,
+    event_publisher: EventPublisher,
+    metrics: Metrics,
+    settings: &Settings,
+) -> Router {
     // Define routes.
-    let app = Router::new()
+    Router::new()
         .route("/dapr/subscribe", get(list_topic_subscriptions))
         .route("/on-topic-event", post(on_topic_event))
         .with_state(HttpEventServiceState {
-            product_variant_collection,
-            user_collection,
+            repositories,
+            event_publisher,
+            metrics,
+            pubsub_name: settings.dapr.pubsub_name.clone(),
+            topics: settings.topics.clone(),
+        })
+}
+
+/// State of the GraphQL handlers.
+#[derive(Clone)]
+struct GraphQLState {
+    schema: Schema<Query, Mutation, Subscription>,
+    /// Verification of the identity of the user of a request.
+    authenticator: Authenticator,
+}
+
+/// Returns Router of the GraphQL and Dapr endpoints, which operate on the given repositories.
+///
+/// Health and metrics endpoints depend on MongoDB directly and are added by `start_service`.
+///
+/// * `repositories` - Storage of the service.
+/// * `wishlist_change_broker` - Broker that notifies GraphQL subscriptions of wishlist changes.
+/// * `event_publisher` - Publisher of the Dapr sidecar, used to forward malformed events to the dead-letter topic.
+/// * `metrics` - Metrics that record GraphQL requests and received events.
+/// * `tracer` - Tracer of GraphQL resolver spans, `None` if traces are not exported.
+/// * `authenticator` - Verification of the identity of the user of GraphQL requests.
+/// * `settings` - Settings of the service.
+fn build_service_router(
+    repositories: Repositories,
+    wishlist_change_broker: WishlistChangeBroker,
+    event_publisher: EventPublisher,
+    metrics: Metrics,
+    tracer: Option<Tracer>,
+    authenticator: Authenticator,
+    settings: &Settings,
+) -> Router {
+    let mut schema_builder = Schema::build(Query, Mutation, Subscription)
+        .extension(Logger)
+        .extension(GraphQLMetrics(metrics.clone()));
+    if let Some(tracer) = tracer {
+        schema_builder = schema_builder.extension(OpenTelemetry::new(tracer));
+    }
+    let schema = schema_builder
+        .data(repositories.clone())
+        .data(wishlist_change_broker)
+        .data(settings.clone())
+        .enable_federation()
+        .finish();
+
+    let graphiql = Router::new()
+        .route("/", get(graphiql).post(graphql_handler))
+        .route("/ws", get(graphql_ws_handler))
+        .with_state(GraphQLState {
+            schema,
+            authenticator,
         });
-    app
+    let dapr_router = build_dapr_router(repositories, event_publisher, metrics, settings);
+    Router::new().merge(graphiql).merge(dapr_router)
 }
 
 /// Can be used to insert dummy wishlist data in the MongoDB database.
@@ -96,78 +201,339 @@ async fn insert_dummy_data(collection: &Collection<Wishlist>) {
     let wishlists: Vec<Wishlist> = vec![Wishlist {
         _id: Uuid::new(),
         user: User { _id: Uuid::new() },
-        internal_product_variants: HashSet::new(),
+        internal_product_variants: Vec::new(),
         name: String::from("test"),
         created_at: DateTime::now(),
         last_updated_at: DateTime::now(),
+        visibility: WishlistVisibility::default(),
+        share_token: None,
+        members: Vec::new(),
+        deleted_at: None,
+        version: 0,
     }];
     collection.insert_many(wishlists, None).await.unwrap();
 }
 
-/// Command line argument to toggle schema generation instead of service execution.
+/// Command line arguments to toggle schema generation or config printing instead of service execution.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Generates GraphQL schema in `./schemas/wishlist.graphql`.
+    /// Generates GraphQL schema in the configured `schema_path`, `./schemas/wishlist.graphql` by default.
     #[arg(long)]
     generate_schema: bool,
+    /// Prints the effective settings as TOML and exits.
+    #[arg(long)]
+    print_config: bool,
+    #[command(flatten)]
+    settings: SettingsArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Maintenance commands that run instead of the service.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates missing MongoDB indexes and logs indexes that differ from their declaration.
+    EnsureIndexes {
+        /// Only logs the drift of the indexes without creating missing indexes, exits with code 1 if there is drift.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Manages the schema migrations of wishlist documents.
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+}
+
+/// Actions of the `migrate` command.
+#[derive(Subcommand, Debug)]
+enum MigrateAction {
+    /// Applies all pending migrations in order.
+    Up,
+    /// Prints the schema version and the applied and pending migrations.
+    Status,
+    /// Prints the amount of wishlists each pending migration would modify, without modifying them.
+    DryRun,
 }
 
 /// Activates logger and parses argument for optional schema generation. Otherwise starts gRPC and GraphQL server.
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    SimpleLogger::new().init().unwrap();
+    init_logging().expect("Installing logger failed.");
 
     let args = Args::parse();
-    if args.generate_schema {
-        let schema = Schema::build(Query, Mutation, EmptySubscription).finish();
-        let mut file = File::create("./schemas/wishlist.graphql")?;
+    let settings = match Settings::load(&args.settings) {
+        Ok(settings) => settings,
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    configure_logging(&settings.logging);
+    if args.print_config {
+        print!("{}", settings.to_redacted_toml());
+    } else if args.generate_schema {
+        let schema = Schema::build(Query, Mutation, Subscription).finish();
+        let mut file = File::create(&settings.schema_path)?;
         let sdl_export_options = SDLExportOptions::new().federation();
         let schema_sdl = schema.sdl_with_options(sdl_export_options);
         file.write_all(schema_sdl.as_bytes())?;
-        info!("GraphQL schema: ./schemas/wishlist.graphql was successfully generated!");
+        info!(
+            "GraphQL schema: {} was successfully generated!",
+            settings.schema_path
+        );
+    } else if let Some(command) = args.command {
+        run_command(command, settings).await;
     } else {
-        start_service().await;
+        start_service(settings).await;
+    }
+    Ok(())
+}
+
+/// Runs a maintenance command, exits with code 1 if it fails.
+async fn run_command(command: Command, settings: Settings) {
+    let client = db_connection(&settings.mongodb, &Metrics::new()).await;
+    let db_client: Database = client.database(&settings.mongodb.database);
+    match command {
+        Command::EnsureIndexes { check } => {
+            let result = match check {
+                true => check_indexes(&db_client, &settings.collections).await,
+                false => ensure_indexes(&db_client, &settings.collections).await,
+            };
+            match result {
+                Ok(drift) if check && !drift.is_empty() => {
+                    drift
+                        .iter()
+                        .for_each(|index_drift| error!("{}", index_drift));
+                    std::process::exit(1);
+                }
+                Ok(_) => info!("MongoDB indexes are up to date."),
+                Err(error) => {
+                    error!("Checking MongoDB indexes failed: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Migrate { action } => {
+            if let Err(error) = run_migrate_action(action, &db_client, &settings).await {
+                error!("Migrating failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Runs an action of the `migrate` command, prints status and dry run results to stdout.
+async fn run_migrate_action(
+    action: MigrateAction,
+    db_client: &Database,
+    settings: &Settings,
+) -> mongodb::error::Result<()> {
+    let collections = &settings.collections;
+    match action {
+        MigrateAction::Up => {
+            let applied = migrate_up(db_client, collections).await?;
+            info!("Applied {} migrations.", applied.len());
+        }
+        MigrateAction::Status => {
+            let status = migration_status(db_client, collections).await?;
+            println!("Schema version: {}", status.schema_version());
+            for applied_migration in &status.applied {
+                println!(
+                    "applied {:>3} {} at {}",
+                    applied_migration._id, applied_migration.name, applied_migration.applied_at
+                );
+            }
+            for migration in &status.pending {
+                println!("pending {}", migration);
+            }
+        }
+        MigrateAction::DryRun => {
+            let counts = migration_dry_run(db_client, collections).await?;
+            if counts.is_empty() {
+                println!("No migrations are pending.");
+            }
+            for (migration, count) in counts {
+                println!("{} would modify {} wishlists", migration, count);
+            }
+        }
     }
     Ok(())
 }
 
 /// Describes the handler for GraphQL requests.
 ///
-/// Parses the "Authenticate-User" header and writes it in the context data of the specfic request.
-/// Then executes the GraphQL schema with the request.
+/// Retrieves the identity of the user with the configured authenticator and writes it in the context data of the specfic request.
+/// Then executes the GraphQL schema with the request in the trace of the `traceparent` header.
+/// Logs of the execution carry the user and the operation name.
 async fn graphql_handler(
-    State(schema): State<Schema<Query, Mutation, EmptySubscription>>,
+    State(GraphQLState {
+        schema,
+        authenticator,
+    }): State<GraphQLState>,
     headers: HeaderMap,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     let mut req = req.into_inner();
-    if let Ok(authenticate_user_header) = AuthorizedUserHeader::try_from(&headers) {
-        req = req.data(authenticate_user_header);
+    let mut request_context = RequestContext {
+        operation_name: req.operation_name.clone(),
+        ..current_request_context()
+    };
+    match authenticator.authorized_user(&headers) {
+        Ok(authenticate_user_header) => {
+            request_context.user_id = Some(authenticate_user_header.id());
+            req = req.data(authenticate_user_header);
+        }
+        Err(error) => req = req.data(AuthenticationFailure(error)),
     }
-    schema.execute(req).await.into()
+    request_context
+        .scope(
+            schema
+                .execute(req)
+                .with_context(context_from_headers(&headers)),
+        )
+        .await
+        .into()
 }
 
-/// Starts wishlist service on port 8000.
-async fn start_service() {
-    let client = db_connection().await;
-    let db_client: Database = client.database("wishlist-database");
+/// Describes the handler for GraphQL subscriptions over WebSocket.
+///
+/// Retrieves the identity of the user of the upgrade request with the configured authenticator and writes it in the context data of the connection.
+/// Then serves the GraphQL schema over the graphql-ws protocol in the trace of the `traceparent` header.
+async fn graphql_ws_handler(
+    State(GraphQLState {
+        schema,
+        authenticator,
+    }): State<GraphQLState>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    websocket: WebSocketUpgrade,
+) -> Response {
+    let mut data = Data::default();
+    let mut request_context = current_request_context();
+    match authenticator.authorized_user(&headers) {
+        Ok(authenticate_user_header) => {
+            request_context.user_id = Some(authenticate_user_header.id());
+            data.insert(authenticate_user_header);
+        }
+        Err(error) => data.insert(AuthenticationFailure(error)),
+    }
+    let trace_context = context_from_headers(&headers);
+    websocket
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            request_context.scope(
+                GraphQLWebSocket::new(stream, schema, protocol)
+                    .with_data(data)
+                    .serve()
+                    .with_context(trace_context),
+            )
+        })
+}
 
-    let schema = Schema::build(Query, Mutation, EmptySubscription)
-        .extension(Logger)
-        .data(db_client.clone())
-        .enable_federation()
-        .finish();
+/// Starts wishlist service on the configured address.
+async fn start_service(settings: Settings) {
+    let tracer = match init_tracing(&settings.tracing) {
+        Ok(tracer) => tracer,
+        Err(error) => {
+            error!("Setting up trace export failed: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let authenticator = match Authenticator::from_settings(&settings.authentication).await {
+        Ok(authenticator) => authenticator,
+        Err(error) => {
+            error!("Setting up authentication failed: {}", error);
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(authenticator.clone().refresh_keys());
+    let metrics = Metrics::new();
+    let client = db_connection(&settings.mongodb, &metrics).await;
+    let db_client: Database = client.database(&settings.mongodb.database);
+    let collections = &settings.collections;
+    match check_schema_version(&db_client, collections).await {
+        Ok(pending) if pending.is_empty() => {}
+        Ok(pending) => {
+            error!(
+                "{} migrations are pending, apply them with `migrate up` before starting the service.",
+                pending.len()
+            );
+            std::process::exit(1);
+        }
+        Err(error) => {
+            error!("Checking schema version failed: {}", error);
+            std::process::exit(1);
+        }
+    }
+    if let Err(error) = ensure_indexes(&db_client, collections).await {
+        error!("Creating MongoDB indexes failed: {}", error);
+        std::process::exit(1);
+    }
 
-    let graphiql = Router::new()
-        .route("/", get(graphiql).post(graphql_handler))
-        .route("/health", get(StatusCode::OK))
-        .with_state(schema);
-    let dapr_router = build_dapr_router(db_client).await;
-    let app = Router::new().merge(graphiql).merge(dapr_router);
+    let event_publisher = EventPublisher::from_settings(&settings.dapr);
+    let outbox_signal = OutboxSignal::default();
+    tokio::spawn(drain_outbox(
+        db_client.collection::<OutboxEntry>(&collections.outbox),
+        event_publisher.clone(),
+        outbox_signal.clone(),
+    ));
+    tokio::spawn(purge_delivered_entries(
+        db_client.collection::<OutboxEntry>(&collections.outbox),
+        settings.limits.outbox_retention(),
+    ));
+
+    tokio::spawn(purge_trashed_wishlists(
+        db_client.collection::<Wishlist>(&collections.wishlists),
+        settings.limits.trash_retention(),
+    ));
+
+    let repositories = Repositories::new(MongoDbRepository::new(
+        client,
+        &db_client,
+        &settings,
+        outbox_signal,
+    ));
+
+    let wishlist_change_broker = WishlistChangeBroker::default();
+    tokio::spawn(
+        wishlist_change_broker
+            .clone()
+            .watch_change_stream(db_client.collection::<Wishlist>(&collections.wishlists)),
+    );
+
+    let health_router = Router::new()
+        .route("/health", get(live))
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+        .with_state(HealthState {
+            db_client: db_client.clone(),
+            event_publisher: event_publisher.clone(),
+            settings: settings.health.clone(),
+        });
+    let metrics_router = Router::new()
+        .route("/metrics", get(export_metrics))
+        .with_state(MetricsState {
+            metrics: metrics.clone(),
+            wishlist_collection: db_client.collection::<Wishlist>(&collections.wishlists),
+        });
+    let app = build_service_router(
+        repositories,
+        wishlist_change_broker,
+        event_publisher,
+        metrics,
+        tracer,
+        authenticator,
+        &settings,
+    )
+    .merge(health_router)
+    .merge(metrics_router)
+    .layer(middleware::from_fn(correlate_request));
 
-    info!("GraphiQL IDE: http://0.0.0.0:8080");
-    Server::bind(&"0.0.0.0:8080".parse().unwrap())
+    let address = format!("{}:{}", settings.server.host, settings.server.port);
+    info!("GraphiQL IDE: http://{}", address);
+    Server::bind(&address.parse().expect("Server address is not valid."))
         .serve(app.into_make_service())
         .await
         .unwrap();
@@ -0,0 +1,306 @@
+use std::{sync::Arc, time::Instant};
+
+use async_graphql::{
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextExecute, NextResolve, ResolveInfo,
+    },
+    Response, ServerResult, Value,
+};
+use axum::{extract::State, http::header, http::StatusCode, response::IntoResponse};
+use bson::{doc, Bson, Document};
+use futures::TryStreamExt;
+use log::warn;
+use mongodb::{
+    event::command::{
+        CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent,
+    },
+    Collection,
+};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::wishlist::Wishlist;
+
+/// Label of GraphQL operations that are sent without an operation name.
+const ANONYMOUS_OPERATION: &str = "anonymous";
+/// Label of GraphQL errors without a `code` extension.
+const UNSPECIFIED_ERROR_CODE: &str = "UNSPECIFIED";
+/// Label of Dapr events whose topic is not subscribed or could not be parsed.
+const UNKNOWN_TOPIC: &str = "unknown";
+
+/// Prometheus metrics of the service.
+///
+/// Cloning is cheap, all clones record to the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    graphql_requests: IntCounterVec,
+    graphql_request_duration: HistogramVec,
+    graphql_field_duration: HistogramVec,
+    graphql_errors: IntCounterVec,
+    mongodb_command_duration: HistogramVec,
+    dapr_events: IntCounterVec,
+    wishlists_total: IntGauge,
+    wishlist_items_average: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates the metrics and registers them in a registry of their own.
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("wishlist")), None)
+            .expect("Metrics registry prefix is not valid.");
+        let graphql_requests = IntCounterVec::new(
+            Opts::new("graphql_requests_total", "GraphQL requests by operation."),
+            &["operation"],
+        )
+        .unwrap();
+        let graphql_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_request_duration_seconds",
+                "Execution time of GraphQL requests by operation.",
+            ),
+            &["operation"],
+        )
+        .unwrap();
+        let graphql_field_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_field_duration_seconds",
+                "Execution time of GraphQL field resolvers by parent type and field.",
+            ),
+            &["parent_type", "field"],
+        )
+        .unwrap();
+        let graphql_errors = IntCounterVec::new(
+            Opts::new(
+                "graphql_errors_total",
+                "GraphQL errors by the `code` error extension.",
+            ),
+            &["code"],
+        )
+        .unwrap();
+        let mongodb_command_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mongodb_command_duration_seconds",
+                "Execution time of MongoDB commands by command name and outcome.",
+            ),
+            &["command", "outcome"],
+        )
+        .unwrap();
+        let dapr_events = IntCounterVec::new(
+            Opts::new(
+                "dapr_events_total",
+                "Dapr events received by topic and outcome.",
+            ),
+            &["topic", "outcome"],
+        )
+        .unwrap();
+        let wishlists_total = IntGauge::new(
+            "wishlists",
+            "Wishlists that are not in the trash, updated on scrape.",
+        )
+        .unwrap();
+        let wishlist_items_average = Gauge::new(
+            "wishlist_items_average",
+            "Average amount of items per wishlist that is not in the trash, updated on scrape.",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(graphql_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(graphql_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(graphql_field_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(graphql_errors.clone())).unwrap();
+        registry
+            .register(Box::new(mongodb_command_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(dapr_events.clone())).unwrap();
+        registry
+            .register(Box::new(wishlists_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(wishlist_items_average.clone()))
+            .unwrap();
+        Self {
+            registry,
+            graphql_requests,
+            graphql_request_duration,
+            graphql_field_duration,
+            graphql_errors,
+            mongodb_command_duration,
+            dapr_events,
+            wishlists_total,
+            wishlist_items_average,
+        }
+    }
+
+    /// Records a received Dapr event.
+    ///
+    /// * `topic` - Topic of the event, `None` if the payload could not be parsed or the topic is not subscribed.
+    /// * `outcome` - Status the event was answered with.
+    pub fn record_dapr_event(&self, topic: Option<&str>, outcome: &str) {
+        self.dapr_events
+            .with_label_values(&[topic.unwrap_or(UNKNOWN_TOPIC), outcome])
+            .inc();
+    }
+
+    /// Encodes all metrics in the Prometheus text format.
+    fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// GraphQL extension that records request, field and error metrics.
+pub struct GraphQLMetrics(pub Metrics);
+
+impl ExtensionFactory for GraphQLMetrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(GraphQLMetricsExtension(self.0.clone()))
+    }
+}
+
+struct GraphQLMetricsExtension(Metrics);
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for GraphQLMetricsExtension {
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let operation = operation_name.unwrap_or(ANONYMOUS_OPERATION);
+        let start = Instant::now();
+        let response = next.run(ctx, operation_name).await;
+        self.0
+            .graphql_requests
+            .with_label_values(&[operation])
+            .inc();
+        self.0
+            .graphql_request_duration
+            .with_label_values(&[operation])
+            .observe(start.elapsed().as_secs_f64());
+        for error in &response.errors {
+            let code = match error.extensions.as_ref().and_then(|e| e.get("code")) {
+                Some(Value::String(code)) => code.as_str(),
+                Some(Value::Enum(code)) => code.as_str(),
+                _ => UNSPECIFIED_ERROR_CODE,
+            };
+            self.0.graphql_errors.with_label_values(&[code]).inc();
+        }
+        response
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        let parent_type = info.parent_type.to_string();
+        let field = info.name.to_string();
+        let start = Instant::now();
+        let result = next.run(ctx, info).await;
+        self.0
+            .graphql_field_duration
+            .with_label_values(&[&parent_type, &field])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+}
+
+/// MongoDB command monitor that records command latencies.
+pub struct MongoDbMetrics(pub Metrics);
+
+impl CommandEventHandler for MongoDbMetrics {
+    fn handle_command_started_event(&self, _event: CommandStartedEvent) {}
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.0
+            .mongodb_command_duration
+            .with_label_values(&[&event.command_name, "success"])
+            .observe(event.duration.as_secs_f64());
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        self.0
+            .mongodb_command_duration
+            .with_label_values(&[&event.command_name, "failure"])
+            .observe(event.duration.as_secs_f64());
+    }
+}
+
+/// State of the metrics endpoint.
+#[derive(Clone)]
+pub struct MetricsState {
+    pub metrics: Metrics,
+    pub wishlist_collection: Collection<Wishlist>,
+}
+
+/// HTTP endpoint for Prometheus scrapes.
+///
+/// Updates the wishlist gauges from MongoDB before encoding all metrics.
+/// If MongoDB is not reachable, the gauges keep their last values.
+pub async fn export_metrics(State(state): State<MetricsState>) -> impl IntoResponse {
+    update_wishlist_gauges(&state).await;
+    match state.metrics.encode() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, TextEncoder::new().format_type())],
+            body,
+        )
+            .into_response(),
+        Err(error) => {
+            warn!("Encoding metrics failed: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Counts wishlists that are not in the trash and their average amount of items.
+async fn update_wishlist_gauges(state: &MetricsState) {
+    let pipeline = vec![
+        doc! {"$match": {"deleted_at": null}},
+        doc! {"$group": {
+            "_id": null,
+            "count": {"$sum": 1},
+            "items_average": {"$avg": {"$size": {"$ifNull": ["$internal_product_variants", []]}}},
+        }},
+    ];
+    let result: Result<Vec<Document>, _> =
+        match state.wishlist_collection.aggregate(pipeline, None).await {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(error) => Err(error),
+        };
+    match result {
+        Ok(documents) => {
+            let (count, items_average) = match documents.first() {
+                Some(document) => (
+                    match document.get("count") {
+                        Some(Bson::Int32(count)) => i64::from(*count),
+                        Some(Bson::Int64(count)) => *count,
+                        _ => 0,
+                    },
+                    document.get_f64("items_average").unwrap_or(0.0),
+                ),
+                None => (0, 0.0),
+            };
+            state.metrics.wishlists_total.set(count);
+            state.metrics.wishlist_items_average.set(items_average);
+        }
+        Err(error) => warn!("Aggregating wishlist metrics failed in MongoDB: {}", error),
+    }
+}
@@ -0,0 +1,422 @@
+use std::fmt;
+
+use bson::{doc, DateTime, Document};
+use futures::TryStreamExt;
+use log::info;
+use mongodb::{
+    options::{UpdateModifications, UpdateOptions},
+    Collection, Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    settings::CollectionSettings,
+    wishlist::Wishlist,
+    wishlist_item::{default_quantity, WishlistItemPriority},
+};
+
+/// Record of an applied migration, stored in the migrations collection.
+///
+/// The schema version of the wishlist documents is the highest applied version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    /// Schema version the migration leads to.
+    pub _id: u32,
+    /// Name of the migration.
+    pub name: String,
+    /// Timestamp when the migration was applied.
+    pub applied_at: DateTime,
+    /// Amount of wishlists the migration modified.
+    pub modified_count: u64,
+}
+
+/// Migration of the wishlist documents, each migration leads to the next schema version.
+///
+/// Migrations only modify documents that still have the old shape, so applying one again is no error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migration {
+    /// Converts plain product variant references to items with metadata.
+    ProductVariantReferencesToItems,
+    /// Initializes the version of wishlists that were written before versioning.
+    MissingVersions,
+}
+
+impl Migration {
+    /// All migrations, in the order they are applied.
+    pub const ALL: [Self; 2] = [Self::ProductVariantReferencesToItems, Self::MissingVersions];
+
+    /// Schema version the migration leads to.
+    pub fn schema_version(self) -> u32 {
+        match self {
+            Self::ProductVariantReferencesToItems => 1,
+            Self::MissingVersions => 2,
+        }
+    }
+
+    /// Name of the migration.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ProductVariantReferencesToItems => "product_variant_references_to_items",
+            Self::MissingVersions => "missing_versions",
+        }
+    }
+
+    /// Filter of the wishlist documents that still need the migration.
+    fn filter(self) -> Document {
+        match self {
+            Self::ProductVariantReferencesToItems => {
+                doc! {"internal_product_variants": {"$elemMatch": {"added_at": {"$exists": false}}}}
+            }
+            Self::MissingVersions => doc! {"version": {"$exists": false}},
+        }
+    }
+
+    /// Update of the wishlist documents that still need the migration.
+    fn update(self) -> UpdateModifications {
+        match self {
+            // References without `added_at` get the `last_updated_at` timestamp of their wishlist, the default quantity and priority.
+            Self::ProductVariantReferencesToItems => UpdateModifications::Pipeline(vec![doc! {
+                "$set": {
+                    "internal_product_variants": {
+                        "$map": {
+                            "input": "$internal_product_variants",
+                            "as": "item",
+                            "in": {
+                                "$mergeObjects": [
+                                    {
+                                        "added_at": "$last_updated_at",
+                                        "note": null,
+                                        "quantity": default_quantity(),
+                                        "priority": WishlistItemPriority::default().as_str(),
+                                    },
+                                    "$$item"
+                                ]
+                            }
+                        }
+                    }
+                }
+            }]),
+            // Conditional updates match the version exactly, which requires the field to be present.
+            Self::MissingVersions => UpdateModifications::Document(doc! {"$set": {"version": 0}}),
+        }
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>3} {}", self.schema_version(), self.name())
+    }
+}
+
+/// Applied and pending migrations of a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    /// Applied migrations, ordered by schema version.
+    pub applied: Vec<AppliedMigration>,
+    /// Migrations that are not applied yet, in the order they are applied.
+    pub pending: Vec<Migration>,
+}
+
+impl MigrationStatus {
+    /// Schema version of the wishlist documents, `0` if no migration is applied.
+    pub fn schema_version(&self) -> u32 {
+        self.applied
+            .iter()
+            .map(|migration| migration._id)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Retrieves the applied and pending migrations.
+pub async fn migration_status(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<MigrationStatus> {
+    let mut applied: Vec<AppliedMigration> = migration_collection(db_client, collections)
+        .find(None, None)
+        .await?
+        .try_collect()
+        .await?;
+    applied.sort_by_key(|migration| migration._id);
+    let pending = Migration::ALL
+        .into_iter()
+        .filter(|migration| {
+            applied
+                .iter()
+                .all(|applied_migration| applied_migration._id != migration.schema_version())
+        })
+        .collect();
+    Ok(MigrationStatus { applied, pending })
+}
+
+/// Counts the wishlists each pending migration would modify, without modifying them.
+///
+/// Counts of later migrations can be lower than when they are applied, as earlier migrations can produce their old shape.
+pub async fn migration_dry_run(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<Vec<(Migration, u64)>> {
+    let wishlist_collection = db_client.collection::<Wishlist>(&collections.wishlists);
+    let mut counts = Vec::new();
+    for migration in migration_status(db_client, collections).await?.pending {
+        let count = wishlist_collection
+            .count_documents(migration.filter(), None)
+            .await?;
+        counts.push((migration, count));
+    }
+    Ok(counts)
+}
+
+/// Applies the pending migrations in order and records each applied migration.
+///
+/// Returns the records of the migrations applied now.
+pub async fn migrate_up(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<Vec<AppliedMigration>> {
+    let wishlist_collection = db_client.collection::<Wishlist>(&collections.wishlists);
+    let migration_collection = migration_collection(db_client, collections);
+    let mut applied = Vec::new();
+    for migration in migration_status(db_client, collections).await?.pending {
+        let result = wishlist_collection
+            .update_many(migration.filter(), migration.update(), None)
+            .await?;
+        let applied_migration = record(migration, result.modified_count);
+        insert_record(&migration_collection, &applied_migration).await?;
+        info!(
+            "Applied migration: `{}` to schema version {}, modified {} wishlists.",
+            migration.name(),
+            migration.schema_version(),
+            result.modified_count
+        );
+        applied.push(applied_migration);
+    }
+    Ok(applied)
+}
+
+/// Checks that the wishlist documents have the latest schema version, which is required to serve.
+///
+/// A database without wishlists and without applied migrations is initialized with the latest schema version,
+/// as there are no documents of an older shape. Replicas starting concurrently initialize it only once.
+/// Returns the pending migrations, which need to be applied with `migrate up` before serving.
+pub async fn check_schema_version(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> mongodb::error::Result<Vec<Migration>> {
+    let status = migration_status(db_client, collections).await?;
+    let is_new_database = status.applied.is_empty()
+        && db_client
+            .collection::<Wishlist>(&collections.wishlists)
+            .count_documents(None, None)
+            .await?
+            == 0;
+    if !is_new_database {
+        return Ok(status.pending);
+    }
+    let migration_collection = migration_collection(db_client, collections);
+    for migration in status.pending {
+        insert_record(&migration_collection, &record(migration, 0)).await?;
+    }
+    info!(
+        "Initialized new database with schema version {}.",
+        latest_schema_version()
+    );
+    Ok(Vec::new())
+}
+
+/// Schema version of the wishlist documents this version of the service reads and writes.
+pub fn latest_schema_version() -> u32 {
+    Migration::ALL
+        .iter()
+        .map(|migration| migration.schema_version())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Collection of the records of applied migrations.
+fn migration_collection(
+    db_client: &Database,
+    collections: &CollectionSettings,
+) -> Collection<AppliedMigration> {
+    db_client.collection::<AppliedMigration>(&collections.migrations)
+}
+
+/// Record of a migration applied now.
+fn record(migration: Migration, modified_count: u64) -> AppliedMigration {
+    AppliedMigration {
+        _id: migration.schema_version(),
+        name: migration.name().to_string(),
+        applied_at: DateTime::now(),
+        modified_count,
+    }
+}
+
+/// Inserts the record of an applied migration, unless the migration is already recorded.
+///
+/// Keeps the first record if the same migration is recorded concurrently, instead of failing with a duplicate key.
+async fn insert_record(
+    migration_collection: &Collection<AppliedMigration>,
+    applied_migration: &AppliedMigration,
+) -> mongodb::error::Result<()> {
+    let mut record_doc = bson::to_document(applied_migration)?;
+    record_doc.remove("_id");
+    let options = UpdateOptions::builder().upsert(true).build();
+    migration_collection
+        .update_one(
+            doc! {"_id": applied_migration._id},
+            doc! {"$setOnInsert": record_doc},
+            options,
+        )
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_migrations_by_consecutive_schema_versions() {
+        let schema_versions: Vec<u32> = Migration::ALL
+            .iter()
+            .map(|migration| migration.schema_version())
+            .collect();
+
+        let expected_schema_versions: Vec<u32> = (1..=Migration::ALL.len() as u32).collect();
+        assert_eq!(schema_versions, expected_schema_versions);
+        assert_eq!(latest_schema_version(), Migration::ALL.len() as u32);
+    }
+
+    #[test]
+    fn derives_schema_version_of_applied_migrations() {
+        let status = MigrationStatus {
+            applied: vec![record(Migration::ProductVariantReferencesToItems, 3)],
+            pending: vec![Migration::MissingVersions],
+        };
+
+        assert_eq!(status.schema_version(), 1);
+    }
+
+    /// Tests against the MongoDB at `$MONGODB_URI`, run with `cargo test -- --ignored`.
+    ///
+    /// Each test runs on its own database, which is dropped afterwards.
+    mod with_mongodb {
+        use bson::Uuid;
+        use mongodb::Client;
+
+        use super::*;
+        use crate::wishlist_item::WishlistItemPriority;
+
+        /// Empty database on the MongoDB at `$MONGODB_URI`, `mongodb://localhost:27017` if not set.
+        async fn test_database() -> Database {
+            let uri = std::env::var("MONGODB_URI")
+                .unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+            let client = Client::with_uri_str(uri).await.unwrap();
+            client.database(&format!("wishlist-migrations-{}", Uuid::new()))
+        }
+
+        /// Wishlist document of schema version 0, with a plain product variant reference and without version.
+        fn unversioned_wishlist(last_updated_at: DateTime) -> Document {
+            doc! {
+                "_id": Uuid::new(),
+                "user": {"_id": Uuid::new()},
+                "name": "Birthday",
+                "created_at": last_updated_at,
+                "last_updated_at": last_updated_at,
+                "internal_product_variants": [{"_id": Uuid::new()}],
+            }
+        }
+
+        #[tokio::test]
+        #[ignore = "requires MongoDB"]
+        async fn initializes_new_database_once_for_concurrent_replicas() {
+            let db_client = test_database().await;
+            let collections = CollectionSettings::default();
+
+            let (first, second) = tokio::join!(
+                check_schema_version(&db_client, &collections),
+                check_schema_version(&db_client, &collections)
+            );
+            let status = migration_status(&db_client, &collections).await.unwrap();
+            db_client.drop(None).await.unwrap();
+
+            assert_eq!(first.unwrap(), Vec::new());
+            assert_eq!(second.unwrap(), Vec::new());
+            assert_eq!(status.schema_version(), latest_schema_version());
+            assert!(status.pending.is_empty());
+        }
+
+        #[tokio::test]
+        #[ignore = "requires MongoDB"]
+        async fn counts_wishlists_of_pending_migrations_in_dry_run() {
+            let db_client = test_database().await;
+            let collections = CollectionSettings::default();
+            let wishlist_collection = db_client.collection::<Document>(&collections.wishlists);
+            let mut versioned_wishlist = unversioned_wishlist(DateTime::now());
+            versioned_wishlist.insert("version", 3_i64);
+            wishlist_collection
+                .insert_many(
+                    [unversioned_wishlist(DateTime::now()), versioned_wishlist],
+                    None,
+                )
+                .await
+                .unwrap();
+
+            let counts = migration_dry_run(&db_client, &collections).await.unwrap();
+            let status = migration_status(&db_client, &collections).await.unwrap();
+            db_client.drop(None).await.unwrap();
+
+            assert_eq!(
+                counts,
+                vec![
+                    (Migration::ProductVariantReferencesToItems, 2),
+                    (Migration::MissingVersions, 1),
+                ]
+            );
+            assert_eq!(status.schema_version(), 0);
+        }
+
+        #[tokio::test]
+        #[ignore = "requires MongoDB"]
+        async fn migrates_wishlists_to_latest_schema_version() {
+            let db_client = test_database().await;
+            let collections = CollectionSettings::default();
+            let wishlist_collection = db_client.collection::<Document>(&collections.wishlists);
+            let last_updated_at = DateTime::from_millis(1_700_000_000_000);
+            let wishlist = unversioned_wishlist(last_updated_at);
+            wishlist_collection
+                .insert_one(&wishlist, None)
+                .await
+                .unwrap();
+
+            let applied = migrate_up(&db_client, &collections).await.unwrap();
+            let migrated = wishlist_collection
+                .find_one(doc! {"_id": wishlist.get("_id")}, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let applied_again = migrate_up(&db_client, &collections).await.unwrap();
+            db_client.drop(None).await.unwrap();
+
+            let applied_ids: Vec<u32> = applied.iter().map(|migration| migration._id).collect();
+            assert_eq!(applied_ids, vec![1, 2]);
+            assert!(applied
+                .iter()
+                .all(|migration| migration.modified_count == 1));
+            assert!(applied_again.is_empty());
+            let item = migrated.get_array("internal_product_variants").unwrap()[0]
+                .as_document()
+                .unwrap();
+            assert_eq!(item.get_datetime("added_at").unwrap(), &last_updated_at);
+            assert_eq!(item.get("note"), Some(&bson::Bson::Null));
+            assert_eq!(item.get_i64("quantity").unwrap(), default_quantity() as i64);
+            assert_eq!(
+                item.get_str("priority").unwrap(),
+                WishlistItemPriority::default().as_str()
+            );
+            assert_eq!(migrated.get_i32("version").unwrap(), 0);
+            assert!(bson::from_document::<Wishlist>(migrated).is_ok());
+        }
+    }
+}
@@ -0,0 +1,806 @@
+use std::collections::HashSet;
+
+use async_graphql::{Error, MaybeUndefined, Result};
+use bson::{doc, DateTime, Document, Uuid};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    options::{FindOptions, UpdateModifications, UpdateOptions},
+    Client, ClientSession, Collection, Database,
+};
+use mongodb_cursor_pagination::{error::CursorError, FindResult};
+use opentelemetry::KeyValue;
+
+use crate::{
+    base_connection::{BaseConnection, FindResultWrapper},
+    event_publisher::WishlistEventType,
+    foreign_types::ProductVariant,
+    http_event_service::{FailedEvent, ProcessedEvent},
+    outbox::{
+        commit_transaction, enqueue_wishlist_change, query_outbox_backlog, start_transaction,
+        OutboxBacklog, OutboxEntry, OutboxSignal,
+    },
+    repository::{
+        event_states, EventRecordRepository, ProductVariantRepository, UserRepository,
+        WishlistModification, WishlistPageQuery, WishlistRepository,
+    },
+    settings::{Settings, TopicSettings},
+    telemetry::traced,
+    user::User,
+    wishlist::{Wishlist, WishlistVisibility},
+    wishlist_member::WishlistMemberStatus,
+};
+
+/// Repositories stored in MongoDB collections.
+#[derive(Clone)]
+pub struct MongoDbRepository {
+    client: Client,
+    wishlist_collection: Collection<Wishlist>,
+    user_collection: Collection<User>,
+    product_variant_collection: Collection<ProductVariant>,
+    outbox_collection: Collection<OutboxEntry>,
+    processed_event_collection: Collection<ProcessedEvent>,
+    failed_event_collection: Collection<FailedEvent>,
+    topics: TopicSettings,
+    outbox_signal: OutboxSignal,
+}
+
+impl MongoDbRepository {
+    /// Creates repositories on the configured collections of a database.
+    ///
+    /// * `client` - MongoDB client, which starts the transactions of modifications and their events.
+    /// * `db_client` - MongoDB database containing the collections.
+    /// * `settings` - Names of the collections and of the topics of wishlist events.
+    /// * `outbox_signal` - Signal of the outbox drain task, notified after events are written to the outbox.
+    pub fn new(
+        client: Client,
+        db_client: &Database,
+        settings: &Settings,
+        outbox_signal: OutboxSignal,
+    ) -> Self {
+        let collections = &settings.collections;
+        Self {
+            client,
+            wishlist_collection: db_client.collection::<Wishlist>(&collections.wishlists),
+            user_collection: db_client.collection::<User>(&collections.users),
+            product_variant_collection: db_client
+                .collection::<ProductVariant>(&collections.product_variants),
+            outbox_collection: db_client.collection::<OutboxEntry>(&collections.outbox),
+            processed_event_collection: db_client
+                .collection::<ProcessedEvent>(&collections.processed_events),
+            failed_event_collection: db_client
+                .collection::<FailedEvent>(&collections.failed_events),
+            topics: settings.topics.clone(),
+            outbox_signal,
+        }
+    }
+
+    /// Retrieves the wishlists matching a filter within the transaction of `session`.
+    async fn find_many_with_session(
+        &self,
+        filter: Document,
+        session: &mut ClientSession,
+    ) -> mongodb::error::Result<Vec<Wishlist>> {
+        let mut cursor = self
+            .wishlist_collection
+            .find_with_session(filter, None, session)
+            .await?;
+        cursor.stream(session).try_collect().await
+    }
+
+    /// Applies an update to all wishlists matching a filter and writes an update event per wishlist to the outbox,
+    /// within the transaction of `session`.
+    ///
+    /// Trashed wishlists are updated without event, consumers received their deletion when they were trashed.
+    ///
+    /// * `filter` - Filter of the wishlists to update.
+    /// * `update` - Update of each wishlist, which needs to increase the version.
+    /// * `operation` - Description of the update, used in error messages.
+    /// * `session` - Session with the transaction of the update.
+    async fn update_many_with_events(
+        &self,
+        filter: Document,
+        update: Document,
+        operation: &str,
+        session: &mut ClientSession,
+    ) -> Result<()> {
+        let befores = self
+            .find_many_with_session(filter, session)
+            .await
+            .map_err(|error| mongodb_error(operation, error))?;
+        if befores.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<Uuid> = befores.iter().map(|wishlist| wishlist._id).collect();
+        let id_filter = doc! {"_id": {"$in": &ids}};
+        self.wishlist_collection
+            .update_many_with_session(id_filter.clone(), update, None, session)
+            .await
+            .map_err(|error| mongodb_error(operation, error))?;
+        let afters = self
+            .find_many_with_session(id_filter, session)
+            .await
+            .map_err(|error| mongodb_error(operation, error))?;
+        let topic = self.topics.wishlist_topic(WishlistEventType::Updated);
+        for before in befores
+            .iter()
+            .filter(|wishlist| wishlist.deleted_at.is_none())
+        {
+            let maybe_after = afters.iter().find(|wishlist| wishlist._id == before._id);
+            enqueue_wishlist_change(
+                &self.outbox_collection,
+                session,
+                topic,
+                Some(before),
+                maybe_after,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl WishlistRepository for MongoDbRepository {
+    async fn find(&self, id: Uuid) -> Result<Wishlist> {
+        let attributes = vec![
+            KeyValue::new("db.system", "mongodb"),
+            KeyValue::new(
+                "db.mongodb.collection",
+                self.wishlist_collection.name().to_string(),
+            ),
+            KeyValue::new("wishlist.id", id.to_string()),
+        ];
+        traced("query_wishlist", attributes, async {
+            match self
+                .wishlist_collection
+                .find_one(doc! {"_id": id, "deleted_at": null }, None)
+                .await
+            {
+                Ok(Some(wishlist)) => Ok(wishlist),
+                Ok(None) => {
+                    let message = format!("Wishlist with UUID: `{}` not found.", id);
+                    Err(Error::new(message))
+                }
+                Err(error) => Err(mongodb_error("Retrieving wishlist", error)),
+            }
+        })
+        .await
+    }
+
+    async fn find_trashed(&self, id: Uuid) -> Result<Wishlist> {
+        match self
+            .wishlist_collection
+            .find_one(doc! {"_id": id, "deleted_at": {"$ne": null} }, None)
+            .await
+        {
+            Ok(Some(wishlist)) => Ok(wishlist),
+            Ok(None) => {
+                let message = format!("Trashed wishlist with UUID: `{}` not found.", id);
+                Err(Error::new(message))
+            }
+            Err(error) => Err(mongodb_error("Retrieving trashed wishlist", error)),
+        }
+    }
+
+    async fn find_shared(&self, share_token: &str) -> Result<Wishlist> {
+        let filter = doc! {
+            "share_token": share_token,
+            "deleted_at": null,
+            "visibility": {"$in": [WishlistVisibility::LinkShared.as_str(), WishlistVisibility::Public.as_str()]}
+        };
+        match self.wishlist_collection.find_one(filter, None).await {
+            Ok(Some(wishlist)) => Ok(wishlist),
+            Ok(None) => Err(Error::new(
+                "Wishlist for share token not found or sharing was revoked.",
+            )),
+            Err(error) => Err(mongodb_error("Retrieving shared wishlist", error)),
+        }
+    }
+
+    async fn find_trashed_of_user(&self, user_id: Uuid) -> Result<Vec<Wishlist>> {
+        let find_options = FindOptions::builder()
+            .sort(doc! {"deleted_at": -1, "_id": -1})
+            .build();
+        let filter = doc! {"user._id": user_id, "deleted_at": {"$ne": null}};
+        let maybe_wishlists = match self.wishlist_collection.find(filter, find_options).await {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(error) => Err(error),
+        };
+        maybe_wishlists.map_err(|error| mongodb_error("Retrieving trashed wishlists", error))
+    }
+
+    async fn paginate_of_user(&self, query: WishlistPageQuery) -> Result<BaseConnection<Wishlist>> {
+        let find_options = FindOptions::builder()
+            .skip(query.skip)
+            .sort(query.order.sort_doc())
+            .build();
+        let filter = user_wishlists_filter(&query);
+        let pagination = query.cursor_arguments.into_pagination(find_options)?;
+        let document_collection = self.wishlist_collection.clone_with_type::<Document>();
+        let attributes = vec![
+            KeyValue::new("db.system", "mongodb"),
+            KeyValue::new(
+                "db.mongodb.collection",
+                self.wishlist_collection.name().to_string(),
+            ),
+            KeyValue::new("user.id", query.user_id.to_string()),
+            KeyValue::new("pagination.reversed", pagination.reversed),
+        ];
+        traced("paginate_user_wishlists", attributes, async {
+            let maybe_find_results: Result<FindResult<Wishlist>, CursorError> = pagination
+                .paginated_cursor
+                .find(&document_collection, Some(&filter))
+                .await;
+            match maybe_find_results {
+                Ok(find_results) => {
+                    let mut find_result_wrapper = FindResultWrapper(find_results);
+                    if let Some(skip) = pagination.skip {
+                        find_result_wrapper =
+                            find_result_wrapper.limited(pagination.page_size, skip);
+                    }
+                    if pagination.reversed {
+                        find_result_wrapper = find_result_wrapper.reversed();
+                    }
+                    Ok(find_result_wrapper.into())
+                }
+                // The pagination reports errors of the driver as text, which is logged like a driver error.
+                Err(error) => {
+                    error!("Retrieving wishlists failed in MongoDB: {}", error);
+                    Err(Error::new("Retrieving wishlists failed in MongoDB."))
+                }
+            }
+        })
+        .await
+    }
+
+    async fn create(&self, wishlist: &Wishlist) -> Result<Wishlist> {
+        let mut session = start_transaction(&self.client).await?;
+        if let Err(error) = self
+            .wishlist_collection
+            .insert_one_with_session(wishlist, None, &mut session)
+            .await
+        {
+            return Err(mongodb_error("Adding wishlist", error));
+        }
+        let topic = self.topics.wishlist_topic(WishlistEventType::Created);
+        enqueue_wishlist_change(
+            &self.outbox_collection,
+            &mut session,
+            topic,
+            None,
+            Some(wishlist),
+        )
+        .await?;
+        commit_transaction(session).await?;
+        self.outbox_signal.notify();
+        Ok(wishlist.clone())
+    }
+
+    async fn apply(
+        &self,
+        before: &Wishlist,
+        modification: WishlistModification,
+    ) -> Result<Option<Wishlist>> {
+        let id = before._id;
+        let maybe_event_type = modification.event_type();
+        let (filter, update) = build_update(id, modification);
+        let failure = |error| mongodb_error(&format!("Modifying wishlist of id: `{}`", id), error);
+        let event_type = match maybe_event_type {
+            Some(event_type) => event_type,
+            None => {
+                return match self
+                    .wishlist_collection
+                    .update_one(filter, update, None)
+                    .await
+                {
+                    Ok(result) if result.matched_count == 0 => Ok(None),
+                    Ok(_) => WishlistRepository::find(self, id).await.map(Some),
+                    Err(error) => Err(failure(error)),
+                };
+            }
+        };
+        let mut session = start_transaction(&self.client).await?;
+        // Dropping the session without committing aborts the transaction.
+        match self
+            .wishlist_collection
+            .update_one_with_session(filter, update, None, &mut session)
+            .await
+        {
+            Ok(result) if result.matched_count == 0 => return Ok(None),
+            Ok(_) => (),
+            Err(error) => return Err(failure(error)),
+        }
+        let after = match self
+            .wishlist_collection
+            .find_one_with_session(doc! {"_id": id}, None, &mut session)
+            .await
+        {
+            Ok(Some(wishlist)) => wishlist,
+            // Not expected as the update matched the wishlist in the same transaction, treated as not applied.
+            Ok(None) => return Ok(None),
+            Err(error) => return Err(failure(error)),
+        };
+        let (event_before, event_after) = event_states(event_type, before, &after);
+        enqueue_wishlist_change(
+            &self.outbox_collection,
+            &mut session,
+            self.topics.wishlist_topic(event_type),
+            event_before,
+            event_after,
+        )
+        .await?;
+        commit_transaction(session).await?;
+        self.outbox_signal.notify();
+        Ok(Some(after))
+    }
+
+    async fn remove_product_variant(&self, product_variant_id: Uuid) -> Result<()> {
+        let mut session = start_transaction(&self.client).await?;
+        self.update_many_with_events(
+            doc! {"internal_product_variants._id": product_variant_id },
+            doc! {
+                "$pull": {"internal_product_variants": {"_id": product_variant_id }},
+                "$set": {"last_updated_at": DateTime::now()},
+                "$inc": {"version": 1}
+            },
+            "Pruning product variant",
+            &mut session,
+        )
+        .await?;
+        commit_transaction(session).await?;
+        self.outbox_signal.notify();
+        Ok(())
+    }
+
+    async fn remove_user(&self, user_id: Uuid) -> Result<()> {
+        let mut session = start_transaction(&self.client).await?;
+        let owned_filter = doc! {"user._id": user_id };
+        let owned_wishlists = self
+            .find_many_with_session(owned_filter.clone(), &mut session)
+            .await
+            .map_err(|error| mongodb_error("Retrieving wishlists of user", error))?;
+        if let Err(error) = self
+            .wishlist_collection
+            .delete_many_with_session(owned_filter, None, &mut session)
+            .await
+        {
+            return Err(mongodb_error("Deleting wishlists of user", error));
+        }
+        // Consumers received the deletion of trashed wishlists when they were trashed.
+        let topic = self.topics.wishlist_topic(WishlistEventType::Deleted);
+        for wishlist in owned_wishlists
+            .iter()
+            .filter(|wishlist| wishlist.deleted_at.is_none())
+        {
+            enqueue_wishlist_change(
+                &self.outbox_collection,
+                &mut session,
+                topic,
+                Some(wishlist),
+                None,
+            )
+            .await?;
+        }
+        self.update_many_with_events(
+            doc! {"members.user._id": user_id },
+            doc! {"$pull": {"members": {"user._id": user_id }}, "$inc": {"version": 1}},
+            "Removing memberships of user",
+            &mut session,
+        )
+        .await?;
+        commit_transaction(session).await?;
+        self.outbox_signal.notify();
+        Ok(())
+    }
+
+    async fn outbox_backlog(&self, first: Option<u32>) -> Result<OutboxBacklog> {
+        query_outbox_backlog(&self.outbox_collection, first).await
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl UserRepository for MongoDbRepository {
+    async fn find(&self, id: Uuid) -> Result<User> {
+        match self.user_collection.find_one(doc! {"_id": id }, None).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => {
+                let message = format!("User with UUID: `{}` not found.", id);
+                Err(Error::new(message))
+            }
+            Err(error) => Err(mongodb_error("Retrieving user", error)),
+        }
+    }
+
+    async fn insert(&self, id: Uuid) -> Result<()> {
+        upsert_id(&self.user_collection, id)
+            .await
+            .map_err(|error| mongodb_error("Adding user", error))
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<()> {
+        match self
+            .user_collection
+            .delete_one(doc! {"_id": id }, None)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(mongodb_error("Deleting user", error)),
+        }
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl ProductVariantRepository for MongoDbRepository {
+    async fn find_many(&self, ids: &HashSet<Uuid>) -> Result<Vec<ProductVariant>> {
+        let ids: Vec<Uuid> = ids.iter().copied().collect();
+        let maybe_product_variants = match self
+            .product_variant_collection
+            .find(doc! {"_id": { "$in": &ids } }, None)
+            .await
+        {
+            Ok(cursor) => cursor.try_collect().await,
+            Err(error) => Err(error),
+        };
+        maybe_product_variants.map_err(|error| mongodb_error("Retrieving product variants", error))
+    }
+
+    async fn insert(&self, id: Uuid) -> Result<()> {
+        upsert_id(&self.product_variant_collection, id)
+            .await
+            .map_err(|error| mongodb_error("Adding product variant", error))
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<()> {
+        match self
+            .product_variant_collection
+            .delete_one(doc! {"_id": id }, None)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(mongodb_error("Deleting product variant", error)),
+        }
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl EventRecordRepository for MongoDbRepository {
+    async fn is_processed(&self, event_id: &str) -> Result<bool> {
+        match self
+            .processed_event_collection
+            .find_one(doc! {"_id": event_id }, None)
+            .await
+        {
+            Ok(maybe_processed_event) => Ok(maybe_processed_event.is_some()),
+            Err(error) => Err(mongodb_error("Retrieving processed event", error)),
+        }
+    }
+
+    /// Upserts the record, concurrent redeliveries do not fail on a duplicate key.
+    async fn mark_processed(&self, event_id: &str, topic: &str) -> Result<()> {
+        let options = UpdateOptions::builder().upsert(true).build();
+        match self
+            .processed_event_collection
+            .update_one(
+                doc! {"_id": event_id },
+                doc! {"$setOnInsert": {"topic": topic, "processed_at": DateTime::now()}},
+                options,
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(mongodb_error("Recording processed event", error)),
+        }
+    }
+
+    async fn record_failed(&self, failed_event: FailedEvent) -> Result<()> {
+        match self
+            .failed_event_collection
+            .insert_one(failed_event, None)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(mongodb_error("Persisting failed event", error)),
+        }
+    }
+}
+
+/// Builds the filter and the update of a modification of the wishlist of `id`.
+///
+/// The filter only matches if the wishlist meets the precondition of the modification.
+fn build_update(id: Uuid, modification: WishlistModification) -> (Document, UpdateModifications) {
+    let mut filter = match modification.applies_to_trashed() {
+        true => doc! {"_id": id, "deleted_at": {"$ne": null}},
+        false => doc! {"_id": id, "deleted_at": null},
+    };
+    let update = match modification {
+        WishlistModification::Update {
+            expected_version,
+            name,
+            items,
+            visibility,
+            timestamp,
+        } => {
+            filter.insert("version", expected_version as i64);
+            let mut set_doc = doc! {"last_updated_at": timestamp};
+            if let Some(definitely_items) = items {
+                set_doc.insert("internal_product_variants", definitely_items);
+            }
+            if let Some(definitely_name) = name {
+                set_doc.insert("name", definitely_name);
+            }
+            if let Some(definitely_visibility) = visibility {
+                set_doc.insert("visibility", definitely_visibility.as_str());
+            }
+            doc! {"$set": set_doc, "$inc": {"version": 1}}
+        }
+        WishlistModification::AddItems { items, timestamp } => {
+            // Appends only items of product variants that are not on the wishlist yet, which keeps the metadata of present items.
+            let pipeline = vec![doc! {
+                "$set": {
+                    "internal_product_variants": {
+                        "$concatArrays": [
+                            "$internal_product_variants",
+                            {
+                                "$filter": {
+                                    "input": items,
+                                    "as": "item",
+                                    "cond": {"$not": [{"$in": ["$$item._id", "$internal_product_variants._id"]}]}
+                                }
+                            }
+                        ]
+                    },
+                    "last_updated_at": timestamp,
+                    "version": {"$add": ["$version", 1]}
+                }
+            }];
+            return (filter, UpdateModifications::Pipeline(pipeline));
+        }
+        WishlistModification::RemoveItems {
+            product_variant_ids,
+            timestamp,
+        } => doc! {
+            "$pull": {"internal_product_variants": {"_id": {"$in": product_variant_ids}}},
+            "$set": {"last_updated_at": timestamp},
+            "$inc": {"version": 1}
+        },
+        WishlistModification::UpdateItem {
+            product_variant_id,
+            note,
+            quantity,
+            priority,
+            timestamp,
+        } => {
+            filter.insert("internal_product_variants._id", product_variant_id);
+            let mut set_doc = doc! {"last_updated_at": timestamp};
+            let mut unset_doc = Document::new();
+            match note {
+                MaybeUndefined::Value(definitely_note) => {
+                    set_doc.insert("internal_product_variants.$.note", definitely_note);
+                }
+                MaybeUndefined::Null => {
+                    unset_doc.insert("internal_product_variants.$.note", "");
+                }
+                MaybeUndefined::Undefined => (),
+            }
+            if let Some(definitely_quantity) = quantity {
+                set_doc.insert("internal_product_variants.$.quantity", definitely_quantity);
+            }
+            if let Some(definitely_priority) = priority {
+                set_doc.insert(
+                    "internal_product_variants.$.priority",
+                    definitely_priority.as_str(),
+                );
+            }
+            let mut update = doc! {"$set": set_doc, "$inc": {"version": 1}};
+            if !unset_doc.is_empty() {
+                update.insert("$unset", unset_doc);
+            }
+            update
+        }
+        WishlistModification::Share {
+            share_token,
+            visibility,
+            timestamp,
+        } => {
+            let mut set_doc = doc! {"share_token": share_token, "last_updated_at": timestamp};
+            if let Some(definitely_visibility) = visibility {
+                set_doc.insert("visibility", definitely_visibility.as_str());
+            }
+            doc! {"$set": set_doc, "$inc": {"version": 1}}
+        }
+        WishlistModification::RevokeShare {
+            visibility,
+            timestamp,
+        } => {
+            let mut set_doc = doc! {"last_updated_at": timestamp};
+            if let Some(definitely_visibility) = visibility {
+                set_doc.insert("visibility", definitely_visibility.as_str());
+            }
+            doc! {"$set": set_doc, "$unset": {"share_token": ""}, "$inc": {"version": 1}}
+        }
+        WishlistModification::Reserve {
+            product_variant_id,
+            reservation,
+        } => {
+            filter.insert(
+                "internal_product_variants",
+                doc! {"$elemMatch": {"_id": product_variant_id, "reservation": null}},
+            );
+            doc! {"$set": {"internal_product_variants.$.reservation": {
+                "user": {"_id": reservation.user._id},
+                "reserved_at": reservation.reserved_at
+            }}}
+        }
+        WishlistModification::Release {
+            product_variant_id,
+            user_id,
+        } => {
+            filter.insert(
+                "internal_product_variants",
+                doc! {"$elemMatch": {"_id": product_variant_id, "reservation.user._id": user_id}},
+            );
+            doc! {"$set": {"internal_product_variants.$.reservation": null}}
+        }
+        WishlistModification::InviteMember { member } => {
+            filter.insert("members.user._id", doc! {"$ne": member.user._id});
+            let invited_at = member.invited_at;
+            doc! {
+                "$push": {"members": member},
+                "$set": {"last_updated_at": invited_at},
+                "$inc": {"version": 1}
+            }
+        }
+        WishlistModification::AcceptInvitation { user_id, timestamp } => {
+            filter.insert(
+                "members",
+                doc! {"$elemMatch": {"user._id": user_id, "status": WishlistMemberStatus::Invited.as_str()}},
+            );
+            doc! {"$set": {
+                "members.$.status": WishlistMemberStatus::Accepted.as_str(),
+                "members.$.accepted_at": timestamp,
+                "last_updated_at": timestamp
+            }, "$inc": {"version": 1}}
+        }
+        WishlistModification::RemoveMember { user_id, timestamp } => doc! {
+            "$pull": {"members": {"user._id": user_id}},
+            "$set": {"last_updated_at": timestamp},
+            "$inc": {"version": 1}
+        },
+        WishlistModification::Trash { timestamp } => {
+            doc! {"$set": {"deleted_at": timestamp}, "$inc": {"version": 1}}
+        }
+        WishlistModification::Restore => {
+            doc! {"$unset": {"deleted_at": ""}, "$inc": {"version": 1}}
+        }
+    };
+    (filter, UpdateModifications::Document(update))
+}
+
+/// Inserts a document consisting of only `_id`, redeliveries of events do not fail on a duplicate key.
+async fn upsert_id<T: Send + Sync>(
+    collection: &Collection<T>,
+    id: Uuid,
+) -> mongodb::error::Result<()> {
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection
+        .update_one(
+            doc! {"_id": id },
+            doc! {"$setOnInsert": {"_id": id }},
+            options,
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Builds the filter of the wishlists of a page query.
+///
+/// Readers other than the owner are restricted to public wishlists and the ones they are an accepted member of.
+fn user_wishlists_filter(query: &WishlistPageQuery) -> Document {
+    let mut filter = doc! {"user._id": query.user_id, "deleted_at": null};
+    if let Some(reader_id) = query.reader_id {
+        let member =
+            doc! {"user._id": reader_id, "status": WishlistMemberStatus::Accepted.as_str()};
+        filter.insert(
+            "$or",
+            vec![
+                doc! {"visibility": WishlistVisibility::Public.as_str()},
+                doc! {"members": {"$elemMatch": member}},
+            ],
+        );
+    }
+    filter
+}
+
+/// Describes a failed MongoDB operation.
+///
+/// The details of the MongoDB error are only logged, as they may contain hosts, namespaces or write concerns.
+fn mongodb_error(operation: &str, error: mongodb::error::Error) -> Error {
+    error!("{} failed in MongoDB: {}", operation, error);
+    Error::new(format!("{} failed in MongoDB.", operation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base_connection::CursorArguments, order_datatypes::WishlistOrderInput};
+
+    /// Page query of the wishlists of a user.
+    fn page_query(user_id: Uuid, reader_id: Option<Uuid>) -> WishlistPageQuery {
+        WishlistPageQuery {
+            user_id,
+            reader_id,
+            order: WishlistOrderInput::default(),
+            skip: None,
+            cursor_arguments: CursorArguments {
+                first: None,
+                after: None,
+                last: None,
+                before: None,
+            },
+        }
+    }
+
+    #[test]
+    fn filters_wishlists_of_owner_outside_trash() {
+        let user_id = Uuid::new();
+
+        let filter = user_wishlists_filter(&page_query(user_id, None));
+
+        assert_eq!(filter, doc! {"user._id": user_id, "deleted_at": null});
+    }
+
+    #[test]
+    fn filters_public_and_member_wishlists_for_other_reader() {
+        let user_id = Uuid::new();
+        let reader_id = Uuid::new();
+
+        let filter = user_wishlists_filter(&page_query(user_id, Some(reader_id)));
+
+        let expected_filter = doc! {
+            "user._id": user_id,
+            "deleted_at": null,
+            "$or": [
+                {"visibility": "public"},
+                {"members": {"$elemMatch": {"user._id": reader_id, "status": "accepted"}}},
+            ],
+        };
+        assert_eq!(filter, expected_filter);
+    }
+
+    #[test]
+    fn hides_details_of_mongodb_error() {
+        let io_error = std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "mongodb-0.mongodb.internal:27017 refused",
+        );
+
+        let error = mongodb_error("Removing user", io_error.into());
+
+        assert_eq!(error.message, "Removing user failed in MongoDB.");
+    }
+
+    #[test]
+    fn unsets_note_of_item_set_to_null() {
+        let product_variant_id = Uuid::new();
+        let timestamp = DateTime::now();
+        let modification = WishlistModification::UpdateItem {
+            product_variant_id,
+            note: MaybeUndefined::Null,
+            quantity: None,
+            priority: None,
+            timestamp,
+        };
+
+        let (_, update) = build_update(Uuid::new(), modification);
+
+        let expected_update = doc! {
+            "$set": {"last_updated_at": timestamp},
+            "$inc": {"version": 1},
+            "$unset": {"internal_product_variants.$.note": ""},
+        };
+        assert!(
+            matches!(update, UpdateModifications::Document(document) if document == expected_update)
+        );
+    }
+}
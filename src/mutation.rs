@@ -1,22 +1,27 @@
 use std::collections::HashSet;
 
-use async_graphql::{Context, Error, Object, Result};
-use bson::Bson;
+use async_graphql::{Context, Error, ErrorExtensions, Object, Result};
 use bson::Uuid;
-use futures::TryStreamExt;
-use mongodb::{
-    bson::{doc, DateTime},
-    Collection, Database,
-};
+use mongodb::bson::DateTime;
+use opentelemetry::KeyValue;
 
-use crate::authentication::authenticate_user;
-use crate::query::query_user;
+use crate::authentication::{
+    authenticate_user, authenticated_user_id, authorize_wishlist, WishlistPermission,
+};
 use crate::user::User;
 use crate::{
     foreign_types::ProductVariant,
-    mutation_input_structs::{CreateWishlistInput, UpdateWishlistInput},
-    query::query_wishlist,
-    wishlist::Wishlist,
+    mutation_input_structs::{
+        CreateWishlistInput, InviteWishlistMemberInput, UpdateWishlistInput,
+        UpdateWishlistItemInput,
+    },
+    repository::{Repositories, WishlistModification},
+    settings::Settings,
+    telemetry::traced,
+    wishlist::{Wishlist, WishlistVisibility},
+    wishlist_change_broker::WishlistChangeBroker,
+    wishlist_item::{WishlistItem, WishlistItemReservation},
+    wishlist_member::{WishlistMember, WishlistMemberStatus},
 };
 
 /// Describes GraphQL wishlist mutations.
@@ -32,34 +37,39 @@ impl Mutation {
         ctx: &Context<'a>,
         #[graphql(desc = "CreateWishlistInput")] input: CreateWishlistInput,
     ) -> Result<Wishlist> {
-        authenticate_user(&ctx, input.user_id)?;
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        validate_input(db_client, &input).await?;
-        let normalized_product_variants: HashSet<ProductVariant> = input
+        authenticate_user(ctx, input.user_id)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let settings = ctx.data::<Settings>()?;
+        validate_input(repositories, settings, &input).await?;
+        let current_timestamp = DateTime::now();
+        let items: Vec<WishlistItem> = input
             .product_variant_ids
             .iter()
-            .map(|id| ProductVariant { _id: id.clone() })
+            .map(|id| WishlistItem::new(*id, current_timestamp))
             .collect();
-        let current_timestamp = DateTime::now();
         let wishlist = Wishlist {
             _id: Uuid::new(),
             user: User { _id: input.user_id },
-            internal_product_variants: normalized_product_variants,
+            internal_product_variants: items,
             name: input.name,
             created_at: current_timestamp,
             last_updated_at: current_timestamp,
+            visibility: WishlistVisibility::default(),
+            share_token: None,
+            members: Vec::new(),
+            deleted_at: None,
+            version: 0,
         };
-        match collection.insert_one(wishlist, None).await {
-            Ok(result) => {
-                let id = uuid_from_bson(result.inserted_id)?;
-                query_wishlist(&collection, id).await
-            }
-            Err(_) => Err(Error::new("Adding wishlist failed in MongoDB.")),
-        }
+        let created_wishlist = repositories.wishlists.create(&wishlist).await?;
+        ctx.data::<WishlistChangeBroker>()?
+            .publish_mutation_result(created_wishlist._id, Some(&created_wishlist));
+        Ok(created_wishlist)
     }
 
-    /// Updates name and/or product_variant_ids of a specific wishlist referenced with an id.
+    /// Updates name, product_variant_ids and/or visibility of a specific wishlist referenced with an id.
+    ///
+    /// Fails with a `VERSION_CONFLICT` error if the wishlist was modified since `expectedVersion`,
+    /// or since it was read if no `expectedVersion` is provided.
     ///
     /// Formats UUIDs as hyphenated lowercase Strings.
     async fn update_wishlist<'a>(
@@ -67,157 +77,563 @@ impl Mutation {
         ctx: &Context<'a>,
         #[graphql(desc = "UpdateWishlistInput")] input: UpdateWishlistInput,
     ) -> Result<Wishlist> {
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        let wishlist = query_wishlist(&collection, input.id).await?;
-        authenticate_user(&ctx, wishlist.user._id)?;
-        let product_variant_collection: Collection<ProductVariant> =
-            db_client.collection::<ProductVariant>("product_variants");
+        let repositories = ctx.data::<Repositories>()?;
+        let settings = ctx.data::<Settings>()?;
+        let wishlist = repositories.wishlists.find(input.id).await?;
+        // Changing the visibility exposes the wishlist, which is reserved to the owner.
+        let permission = match input.visibility {
+            Some(_) => WishlistPermission::Owner,
+            None => WishlistPermission::Write,
+        };
+        authorize_wishlist(ctx, &wishlist, permission)?;
+        let expected_version = input.expected_version.unwrap_or(wishlist.version);
+        if expected_version != wishlist.version {
+            return Err(VersionConflictError {
+                id: input.id,
+                expected_version,
+                current_version: Some(wishlist.version),
+            }
+            .extend());
+        }
+        if let Some(definitely_product_variant_ids) = &input.product_variant_ids {
+            validate_item_count(settings, definitely_product_variant_ids.len())?;
+        }
         let current_timestamp = DateTime::now();
-        update_product_variant_ids(
-            &collection,
-            &product_variant_collection,
-            &input,
-            &current_timestamp,
-        )
-        .await?;
-        update_name(&collection, &input, &current_timestamp).await?;
-        query_wishlist(&collection, input.id).await
+        let items = build_updated_items(repositories, &wishlist, &input, current_timestamp).await?;
+        // Applies all changes in a single update, which only matches if nobody changed the wishlist since it was read.
+        let modification = WishlistModification::Update {
+            expected_version,
+            name: input.name,
+            items,
+            visibility: input.visibility,
+            timestamp: current_timestamp,
+        };
+        match modify_wishlist(ctx, &wishlist, modification).await? {
+            Some(updated_wishlist) => Ok(updated_wishlist),
+            None => {
+                let current_version = repositories
+                    .wishlists
+                    .find(input.id)
+                    .await
+                    .ok()
+                    .map(|w| w.version);
+                Err(VersionConflictError {
+                    id: input.id,
+                    expected_version,
+                    current_version,
+                }
+                .extend())
+            }
+        }
     }
 
-    /// Deletes wishlist of id.
-    async fn delete_wishlist<'a>(
+    /// Adds product variants to a specific wishlist referenced with an id.
+    ///
+    /// Uses an atomic update, concurrent additions do not overwrite each other.
+    /// Product variants that are already on the wishlist keep their item metadata.
+    async fn add_product_variants_to_wishlist<'a>(
         &self,
         ctx: &Context<'a>,
-        #[graphql(desc = "UUID of wishlist to delete.")] id: Uuid,
-    ) -> Result<bool> {
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        let wishlist = query_wishlist(&collection, id).await?;
-        authenticate_user(&ctx, wishlist.user._id)?;
-        if let Err(_) = collection.delete_one(doc! {"_id": id }, None).await {
-            let message = format!("Deleting wishlist of id: `{}` failed in MongoDB.", id);
+        #[graphql(desc = "UUID of wishlist to add product variants to.")] id: Uuid,
+        #[graphql(desc = "UUIDs of product variants to add.")] product_variant_ids: HashSet<Uuid>,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let settings = ctx.data::<Settings>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Write)?;
+        validate_product_variant_ids(repositories, &product_variant_ids).await?;
+        let new_item_count = product_variant_ids
+            .iter()
+            .filter(|id| !wishlist.contains_product_variant(**id))
+            .count();
+        validate_item_count(
+            settings,
+            wishlist.internal_product_variants.len() + new_item_count,
+        )?;
+        let current_timestamp = DateTime::now();
+        let items: Vec<WishlistItem> = product_variant_ids
+            .iter()
+            .map(|id| WishlistItem::new(*id, current_timestamp))
+            .collect();
+        let modification = WishlistModification::AddItems {
+            items,
+            timestamp: current_timestamp,
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, id)
+    }
+
+    /// Removes product variants from a specific wishlist referenced with an id.
+    ///
+    /// Uses an atomic `$pull` update, concurrent removals do not overwrite each other.
+    async fn remove_product_variants_from_wishlist<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to remove product variants from.")] id: Uuid,
+        #[graphql(desc = "UUIDs of product variants to remove.")] product_variant_ids: HashSet<
+            Uuid,
+        >,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Write)?;
+        // Product variants are not validated against the catalog, as variants deleted upstream need to be removable.
+        if let Some(missing_id) = product_variant_ids
+            .iter()
+            .find(|id| !wishlist.contains_product_variant(**id))
+        {
+            let message = format!(
+                "Product variant with the UUID: `{}` is not on wishlist of id: `{}`.",
+                missing_id, id
+            );
             return Err(Error::new(message));
         }
-        Ok(true)
+        let modification = WishlistModification::RemoveItems {
+            product_variant_ids: product_variant_ids.into_iter().collect(),
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, id)
     }
-}
 
-/// Extracts UUID from Bson.
-///
-/// Adding a wishlist returns a UUID in a Bson document. This function helps to extract the UUID.
-fn uuid_from_bson(bson: Bson) -> Result<Uuid> {
-    match bson {
-        Bson::Binary(id) => Ok(id.to_uuid()?),
-        _ => {
+    /// Updates the metadata of a product variant on a specific wishlist.
+    async fn update_wishlist_item<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UpdateWishlistItemInput")] input: UpdateWishlistItemInput,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(input.wishlist_id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Write)?;
+        if !wishlist.contains_product_variant(input.product_variant_id) {
             let message = format!(
-                "Returned id: `{}` needs to be a Binary in order to be parsed as a Uuid",
-                bson
+                "Product variant with the UUID: `{}` is not on wishlist of id: `{}`.",
+                input.product_variant_id, input.wishlist_id
             );
-            Err(Error::new(message))
+            return Err(Error::new(message));
+        }
+        if input.quantity == Some(0) {
+            return Err(Error::new(
+                "Quantity of a wishlist item must be at least 1.",
+            ));
         }
+        let modification = WishlistModification::UpdateItem {
+            product_variant_id: input.product_variant_id,
+            note: input.note,
+            quantity: input.quantity,
+            priority: input.priority,
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, input.wishlist_id)
     }
-}
 
-/// Updates product variant ids of a wishlist.
-///
-/// * `collection` - MongoDB collection to update.
-/// * `input` - `UpdateWishlistInput`.
-async fn update_product_variant_ids(
-    collection: &Collection<Wishlist>,
-    product_variant_collection: &Collection<ProductVariant>,
-    input: &UpdateWishlistInput,
-    current_timestamp: &DateTime,
-) -> Result<()> {
-    if let Some(definitely_product_variant_ids) = &input.product_variant_ids {
-        validate_product_variant_ids(&product_variant_collection, definitely_product_variant_ids)
-            .await?;
-        let normalized_product_variants: Vec<ProductVariant> = definitely_product_variant_ids
+    /// Issues a new share token for a specific wishlist referenced with an id.
+    ///
+    /// Replaces a previous share token, which revokes it.
+    /// Private wishlists become link-shared, so that the token grants read access.
+    async fn share_wishlist<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to share.")] id: Uuid,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        let visibility = match wishlist.visibility {
+            WishlistVisibility::Private => Some(WishlistVisibility::LinkShared),
+            _ => None,
+        };
+        let modification = WishlistModification::Share {
+            share_token: uuid::Uuid::new_v4().simple().to_string(),
+            visibility,
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, id)
+    }
+
+    /// Revokes the share token of a specific wishlist referenced with an id.
+    ///
+    /// Link-shared wishlists become private, public wishlists stay public.
+    async fn revoke_wishlist_share<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to revoke the share token of.")] id: Uuid,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        let visibility = match wishlist.visibility {
+            WishlistVisibility::LinkShared => Some(WishlistVisibility::Private),
+            _ => None,
+        };
+        let modification = WishlistModification::RevokeShare {
+            visibility,
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, id)
+    }
+
+    /// Reserves a product variant on a specific wishlist for the authenticated user, who intends to gift it.
+    ///
+    /// Requires read access to the wishlist, the owner can not reserve items of the own wishlist.
+    /// Fails if the product variant is already reserved.
+    /// The reservation is released automatically when the product variant is removed from the wishlist.
+    async fn reserve_wishlist_item<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist containing the item.")] wishlist_id: Uuid,
+        #[graphql(desc = "UUID of product variant of the item to reserve.")]
+        product_variant_id: Uuid,
+    ) -> Result<Wishlist> {
+        let user_id = authenticated_user_id(ctx)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(wishlist_id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Read)?;
+        if wishlist.user._id == user_id {
+            return Err(Error::new("Owner of a wishlist can not reserve its items."));
+        }
+        if !wishlist.contains_product_variant(product_variant_id) {
+            let message = format!(
+                "Product variant with the UUID: `{}` is not on wishlist of id: `{}`.",
+                product_variant_id, wishlist_id
+            );
+            return Err(Error::new(message));
+        }
+        let modification = WishlistModification::Reserve {
+            product_variant_id,
+            reservation: WishlistItemReservation {
+                user: User { _id: user_id },
+                reserved_at: DateTime::now(),
+            },
+        };
+        match modify_wishlist(ctx, &wishlist, modification).await? {
+            Some(updated_wishlist) => Ok(updated_wishlist),
+            None => {
+                let message = format!(
+                    "Product variant with the UUID: `{}` on wishlist of id: `{}` is already reserved.",
+                    product_variant_id, wishlist_id
+                );
+                Err(Error::new(message))
+            }
+        }
+    }
+
+    /// Releases the reservation of a product variant on a specific wishlist.
+    ///
+    /// Permitted to the reserving user and users with a permissive role.
+    async fn unreserve_wishlist_item<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist containing the item.")] wishlist_id: Uuid,
+        #[graphql(desc = "UUID of product variant of the item to release.")]
+        product_variant_id: Uuid,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(wishlist_id).await?;
+        let maybe_reservation = wishlist
+            .internal_product_variants
             .iter()
-            .map(|id| ProductVariant { _id: id.clone() })
-            .collect();
-        if let Err(_) = collection.update_one(doc!{"_id": input.id }, doc!{"$set": {"internal_product_variants": normalized_product_variants, "last_updated_at": current_timestamp}}, None).await {
-            let message = format!("Updating product_variant_ids of wishlist of id: `{}` failed in MongoDB.", input.id);
-            return Err(Error::new(message))
+            .find(|item| item._id == product_variant_id)
+            .and_then(|item| item.reservation.as_ref());
+        let reservation = match maybe_reservation {
+            Some(reservation) => reservation,
+            None => {
+                let message = format!(
+                    "Product variant with the UUID: `{}` on wishlist of id: `{}` is not reserved.",
+                    product_variant_id, wishlist_id
+                );
+                return Err(Error::new(message));
+            }
+        };
+        authenticate_user(ctx, reservation.user._id)?;
+        let modification = WishlistModification::Release {
+            product_variant_id,
+            user_id: reservation.user._id,
+        };
+        match modify_wishlist(ctx, &wishlist, modification).await? {
+            Some(updated_wishlist) => Ok(updated_wishlist),
+            // The reservation was released concurrently.
+            None => repositories.wishlists.find(wishlist_id).await,
+        }
+    }
+
+    /// Invites a user to collaborate on a specific wishlist.
+    ///
+    /// The member gains the permissions of the role after accepting the invitation.
+    async fn invite_wishlist_member<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "InviteWishlistMemberInput")] input: InviteWishlistMemberInput,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(input.wishlist_id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        if wishlist.user._id == input.user_id || wishlist.is_member(input.user_id) {
+            let message = format!(
+                "User of UUID: `{}` is already owner or member of wishlist of id: `{}`.",
+                input.user_id, input.wishlist_id
+            );
+            return Err(Error::new(message));
+        }
+        validate_user(repositories, input.user_id).await?;
+        let member = WishlistMember {
+            user: User { _id: input.user_id },
+            role: input.role,
+            status: WishlistMemberStatus::Invited,
+            invited_at: DateTime::now(),
+            accepted_at: None,
+        };
+        let modification = WishlistModification::InviteMember { member };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, input.wishlist_id)
+    }
+
+    /// Accepts the invitation of the authenticated user to a specific wishlist.
+    async fn accept_wishlist_invitation<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to accept the invitation of.")] wishlist_id: Uuid,
+    ) -> Result<Wishlist> {
+        let user_id = authenticated_user_id(ctx)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(wishlist_id).await?;
+        let is_invited = wishlist.members.iter().any(|member| {
+            member.user._id == user_id && member.status == WishlistMemberStatus::Invited
+        });
+        if !is_invited {
+            let message = format!(
+                "User of UUID: `{}` has no pending invitation to wishlist of id: `{}`.",
+                user_id, wishlist_id
+            );
+            return Err(Error::new(message));
         }
+        let modification = WishlistModification::AcceptInvitation {
+            user_id,
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, wishlist_id)
     }
-    Ok(())
+
+    /// Removes a member or a pending invitation from a specific wishlist.
+    ///
+    /// Permitted to the owner and to the member itself, which leaves the wishlist.
+    async fn remove_wishlist_member<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to remove the member from.")] wishlist_id: Uuid,
+        #[graphql(desc = "UUID of user to remove.")] user_id: Uuid,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(wishlist_id).await?;
+        if authenticated_user_id(ctx)? != user_id {
+            authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        }
+        if !wishlist.is_member(user_id) {
+            let message = format!(
+                "User of UUID: `{}` is not a member of wishlist of id: `{}`.",
+                user_id, wishlist_id
+            );
+            return Err(Error::new(message));
+        }
+        let modification = WishlistModification::RemoveMember {
+            user_id,
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, wishlist_id)
+    }
+
+    /// Moves wishlist of id to the trash.
+    ///
+    /// Trashed wishlists can be restored until they are purged after the retention period.
+    async fn delete_wishlist<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to delete.")] id: Uuid,
+    ) -> Result<bool> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        let modification = WishlistModification::Trash {
+            timestamp: DateTime::now(),
+        };
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, modification).await?;
+        modified_wishlist(maybe_wishlist, id).map(|_| true)
+    }
+
+    /// Restores wishlist of id from the trash.
+    async fn restore_wishlist<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to restore.")] id: Uuid,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find_trashed(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Owner)?;
+        let maybe_wishlist = modify_wishlist(ctx, &wishlist, WishlistModification::Restore).await?;
+        modified_wishlist(maybe_wishlist, id)
+    }
+}
+
+/// Applies a modification to a wishlist and broadcasts the result to GraphQL subscriptions.
+///
+/// The repository writes the event of the modification to the outbox together with the modification.
+/// Returns `None` if the wishlist does not meet the precondition of the modification.
+///
+/// * `before` - Wishlist as read before the modification.
+/// * `modification` - Modification to apply.
+async fn modify_wishlist(
+    ctx: &Context<'_>,
+    before: &Wishlist,
+    modification: WishlistModification,
+) -> Result<Option<Wishlist>> {
+    let repositories = ctx.data::<Repositories>()?;
+    let maybe_wishlist = repositories.wishlists.apply(before, modification).await?;
+    if let Some(wishlist) = &maybe_wishlist {
+        ctx.data::<WishlistChangeBroker>()?
+            .publish_mutation_result(wishlist._id, Some(wishlist));
+    }
+    Ok(maybe_wishlist)
 }
 
-/// Updates name of a wishlist.
+/// Unwraps the result of a modification without further precondition than the wishlist not being trashed.
+///
+/// Such a modification only does not apply if the wishlist was deleted concurrently.
+fn modified_wishlist(maybe_wishlist: Option<Wishlist>, id: Uuid) -> Result<Wishlist> {
+    maybe_wishlist.ok_or_else(|| {
+        let message = format!("Wishlist with UUID: `{}` not found.", id);
+        Error::new(message)
+    })
+}
+
+/// Builds the items of an update of product_variant_ids of a wishlist, `None` if the product variants are not updated.
+///
+/// Product variants that stay on the wishlist keep their item metadata.
 ///
-/// * `collection` - MongoDB collection to update.
+/// * `repositories` - Repositories to validate product variants against.
+/// * `wishlist` - Wishlist before the update.
 /// * `input` - `UpdateWishlistInput`.
-async fn update_name(
-    collection: &Collection<Wishlist>,
+async fn build_updated_items(
+    repositories: &Repositories,
+    wishlist: &Wishlist,
     input: &UpdateWishlistInput,
-    current_timestamp: &DateTime,
+    current_timestamp: DateTime,
+) -> Result<Option<Vec<WishlistItem>>> {
+    let definitely_product_variant_ids = match &input.product_variant_ids {
+        Some(product_variant_ids) => product_variant_ids,
+        None => return Ok(None),
+    };
+    validate_product_variant_ids(repositories, definitely_product_variant_ids).await?;
+    let normalized_product_variants: Vec<WishlistItem> = definitely_product_variant_ids
+        .iter()
+        .map(|id| {
+            wishlist
+                .internal_product_variants
+                .iter()
+                .find(|item| item._id == *id)
+                .cloned()
+                .unwrap_or_else(|| WishlistItem::new(*id, current_timestamp))
+        })
+        .collect();
+    Ok(Some(normalized_product_variants))
+}
+
+/// Conflict of a mutation with a concurrent change of a wishlist.
+///
+/// Exposed as GraphQL error with the extension code `VERSION_CONFLICT` and the versions involved.
+struct VersionConflictError {
+    /// UUID of the wishlist.
+    id: Uuid,
+    /// Version the mutation expected.
+    expected_version: u64,
+    /// Current version of the wishlist, `None` if the wishlist was deleted concurrently.
+    current_version: Option<u64>,
+}
+
+impl ErrorExtensions for VersionConflictError {
+    fn extend(&self) -> Error {
+        let message = format!(
+            "Wishlist of id: `{}` was modified concurrently, expected version: `{}`.",
+            self.id, self.expected_version
+        );
+        Error::new(message).extend_with(|_, extensions| {
+            extensions.set("code", "VERSION_CONFLICT");
+            extensions.set("expectedVersion", self.expected_version);
+            if let Some(definitely_current_version) = self.current_version {
+                extensions.set("currentVersion", definitely_current_version);
+            }
+        })
+    }
+}
+
+/// Checks if product variants and user in CreateWishlistInput are in the system (repositories populated with events).
+///
+/// Also checks that the amount of product variants does not exceed the limit of items on a wishlist.
+async fn validate_input(
+    repositories: &Repositories,
+    settings: &Settings,
+    input: &CreateWishlistInput,
 ) -> Result<()> {
-    if let Some(definitely_name) = &input.name {
-        let result = collection
-            .update_one(
-                doc! {"_id": input.id },
-                doc! {"$set": {"name": definitely_name, "last_updated_at": current_timestamp}},
-                None,
-            )
-            .await;
-        if let Err(_) = result {
+    let attributes = vec![KeyValue::new(
+        "wishlist.product_variant_count",
+        input.product_variant_ids.len() as i64,
+    )];
+    traced("validate_input", attributes, async {
+        validate_item_count(settings, input.product_variant_ids.len())?;
+        validate_product_variant_ids(repositories, &input.product_variant_ids).await?;
+        validate_user(repositories, input.user_id).await?;
+        Ok(())
+    })
+    .await
+}
+
+/// Checks that the amount of product variants on a wishlist does not exceed the limit of items on a wishlist.
+fn validate_item_count(settings: &Settings, item_count: usize) -> Result<()> {
+    let max_wishlist_items = settings.limits.max_wishlist_items;
+    match item_count > max_wishlist_items {
+        true => {
             let message = format!(
-                "Updating name of wishlist of id: `{}` failed in MongoDB.",
-                input.id
+                "A wishlist can contain at most `{}` product variants.",
+                max_wishlist_items
             );
-            return Err(Error::new(message));
+            Err(Error::new(message))
         }
+        false => Ok(()),
     }
-    Ok(())
-}
-
-/// Checks if product variants and user in CreateWishlistInput are in the system (MongoDB database populated with events).
-async fn validate_input(db_client: &Database, input: &CreateWishlistInput) -> Result<()> {
-    let product_variant_collection: Collection<ProductVariant> =
-        db_client.collection::<ProductVariant>("product_variants");
-    let user_collection: Collection<User> = db_client.collection::<User>("users");
-    validate_product_variant_ids(&product_variant_collection, &input.product_variant_ids).await?;
-    validate_user(&user_collection, input.user_id).await?;
-    Ok(())
 }
 
-/// Checks if product variants are in the system (MongoDB database populated with events).
+/// Checks if product variants are in the system (repositories populated with events).
 ///
 /// Used before adding or modifying product variants / wishlists.
 async fn validate_product_variant_ids(
-    collection: &Collection<ProductVariant>,
+    repositories: &Repositories,
     product_variant_ids: &HashSet<Uuid>,
 ) -> Result<()> {
-    let product_variant_ids_vec: Vec<Uuid> = product_variant_ids.clone().into_iter().collect();
-    match collection
-        .find(doc! {"_id": { "$in": &product_variant_ids_vec } }, None)
-        .await
-    {
-        Ok(cursor) => {
-            let product_variants: Vec<ProductVariant> = cursor.try_collect().await?;
-            product_variant_ids_vec.iter().fold(Ok(()), |_, p| {
-                match product_variants.contains(&ProductVariant { _id: *p }) {
-                    true => Ok(()),
-                    false => {
-                        let message = format!(
-                            "Product variant with the UUID: `{}` is not present in the system.",
-                            p
-                        );
-                        Err(Error::new(message))
-                    }
-                }
-            })
+    let product_variants = repositories
+        .product_variants
+        .find_many(product_variant_ids)
+        .await?;
+    product_variant_ids.iter().try_for_each(|p| {
+        match product_variants.contains(&ProductVariant { _id: *p }) {
+            true => Ok(()),
+            false => {
+                let message = format!(
+                    "Product variant with the UUID: `{}` is not present in the system.",
+                    p
+                );
+                Err(Error::new(message))
+            }
         }
-        Err(_) => Err(Error::new(
-            "Product variants with the specified UUIDs are not present in the system.",
-        )),
-    }
+    })
 }
 
-/// Checks if user is in the system (MongoDB database populated with events).
+/// Checks if user is in the system (repositories populated with events).
 ///
 /// Used before adding wishlists.
-async fn validate_user(collection: &Collection<User>, id: Uuid) -> Result<()> {
-    query_user(&collection, id).await.map(|_| ())
+async fn validate_user(repositories: &Repositories, id: Uuid) -> Result<()> {
+    repositories.users.find(id).await.map(|_| ())
 }
@@ -1,7 +1,12 @@
-use async_graphql::{InputObject, SimpleObject};
+use async_graphql::{InputObject, MaybeUndefined, SimpleObject};
 use bson::Uuid;
 use std::collections::HashSet;
 
+use crate::{
+    wishlist::WishlistVisibility, wishlist_item::WishlistItemPriority,
+    wishlist_member::WishlistMemberRole,
+};
+
 #[derive(SimpleObject, InputObject)]
 pub struct CreateWishlistInput {
     /// UUID of user owning the wishlist.
@@ -20,4 +25,32 @@ pub struct UpdateWishlistInput {
     pub product_variant_ids: Option<HashSet<Uuid>>,
     /// Wishlist name to update
     pub name: Option<String>,
+    /// Visibility of wishlist to update
+    pub visibility: Option<WishlistVisibility>,
+    /// Version of wishlist the update is based on, the update fails if the wishlist was modified since
+    pub expected_version: Option<u64>,
+}
+
+#[derive(InputObject)]
+pub struct UpdateWishlistItemInput {
+    /// UUID of wishlist containing the item.
+    pub wishlist_id: Uuid,
+    /// UUID of product variant of the item to update.
+    pub product_variant_id: Uuid,
+    /// Note to update, `null` removes the note.
+    pub note: MaybeUndefined<String>,
+    /// Desired quantity to update, at least 1.
+    pub quantity: Option<u32>,
+    /// Priority to update.
+    pub priority: Option<WishlistItemPriority>,
+}
+
+#[derive(SimpleObject, InputObject)]
+pub struct InviteWishlistMemberInput {
+    /// UUID of wishlist to invite the user to.
+    pub wishlist_id: Uuid,
+    /// UUID of user to invite.
+    pub user_id: Uuid,
+    /// Role of the member.
+    pub role: WishlistMemberRole,
 }
@@ -1,20 +1,16 @@
 use async_graphql::{Enum, InputObject, SimpleObject};
+use bson::{doc, Document};
 
 /// GraphQL order direction.
-#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Default)]
 pub enum OrderDirection {
     /// Ascending order direction.
+    #[default]
     Asc,
     /// Descending order direction.
     Desc,
 }
 
-impl Default for OrderDirection {
-    fn default() -> Self {
-        Self::Asc
-    }
-}
-
 /// Implements conversion to i32 for MongoDB document sorting.
 impl From<OrderDirection> for i32 {
     fn from(value: OrderDirection) -> Self {
@@ -26,9 +22,10 @@ impl From<OrderDirection> for i32 {
 }
 
 /// Describes the fields that a wishlist can be ordered by.
-#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Default)]
 pub enum WishlistOrderField {
     /// Orders by "id".
+    #[default]
     Id,
     /// Orders by "user_id".
     UserId,
@@ -41,6 +38,15 @@ pub enum WishlistOrderField {
 }
 
 impl WishlistOrderField {
+    /// All fields that a wishlist can be ordered by.
+    pub const ALL: [Self; 5] = [
+        Self::Id,
+        Self::UserId,
+        Self::Name,
+        Self::CreatedAt,
+        Self::LastUpdatedAt,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             WishlistOrderField::Id => "_id",
@@ -52,12 +58,6 @@ impl WishlistOrderField {
     }
 }
 
-impl Default for WishlistOrderField {
-    fn default() -> Self {
-        Self::Id
-    }
-}
-
 /// Specifies the order of wishlists.
 #[derive(SimpleObject, InputObject)]
 pub struct WishlistOrderInput {
@@ -67,6 +67,20 @@ pub struct WishlistOrderInput {
     pub field: Option<WishlistOrderField>,
 }
 
+impl WishlistOrderInput {
+    /// Builds the MongoDB sort document of the order.
+    ///
+    /// `_id` is the tiebreaker of the sort order, which keeps cursors stable.
+    pub fn sort_doc(&self) -> Document {
+        let direction = i32::from(self.direction.unwrap_or_default());
+        let mut sort_doc = doc! {self.field.unwrap_or_default().as_str(): direction};
+        if !sort_doc.contains_key("_id") {
+            sort_doc.insert("_id", direction);
+        }
+        sort_doc
+    }
+}
+
 impl Default for WishlistOrderInput {
     fn default() -> Self {
         Self {
@@ -76,39 +90,30 @@ impl Default for WishlistOrderInput {
     }
 }
 
-/// Describes the fields that a foreign types can be ordered by.
-///
-/// Only the Id valid at the moment.
-#[derive(Enum, Copy, Clone, Eq, PartialEq)]
-pub enum CommonOrderField {
-    /// Orders by "id".
+/// Describes the fields that wishlist items and their product variants can be ordered by.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Default)]
+pub enum WishlistItemOrderField {
+    /// Orders by "id" of the product variant.
+    #[default]
     Id,
+    /// Orders by "added_at".
+    AddedAt,
+    /// Orders by "quantity".
+    Quantity,
+    /// Orders by "priority".
+    Priority,
 }
 
-impl CommonOrderField {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            CommonOrderField::Id => "_id",
-        }
-    }
-}
-
-impl Default for CommonOrderField {
-    fn default() -> Self {
-        Self::Id
-    }
-}
-
-/// Specifies the order of foreign types.
+/// Specifies the order of wishlist items.
 #[derive(SimpleObject, InputObject)]
-pub struct CommonOrderInput {
-    /// Order direction of wishlists.
+pub struct WishlistItemOrderInput {
+    /// Order direction of wishlist items.
     pub direction: Option<OrderDirection>,
-    /// Field that wishlists should be ordered by.
-    pub field: Option<CommonOrderField>,
+    /// Field that wishlist items should be ordered by.
+    pub field: Option<WishlistItemOrderField>,
 }
 
-impl Default for CommonOrderInput {
+impl Default for WishlistItemOrderInput {
     fn default() -> Self {
         Self {
             direction: Some(Default::default()),
@@ -0,0 +1,462 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_graphql::{Error, Result, SimpleObject};
+use bson::{doc, DateTime, Document, Uuid};
+use futures::TryStreamExt;
+use log::{error, info, warn};
+use mongodb::{
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client, ClientSession, Collection,
+};
+use opentelemetry::{
+    trace::{FutureExt, SpanKind, Status, TraceContextExt},
+    KeyValue,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    event_publisher::{EventPublisher, WishlistEventData},
+    telemetry::{context_from_carrier, current_trace_carrier, start_span},
+    wishlist::Wishlist,
+};
+
+/// Interval in which the outbox is polled if no new entries are signaled.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Duration an entry is leased to a single drain task before it can be claimed again.
+const LEASE_DURATION: Duration = Duration::from_secs(30);
+/// Upper bound of the exponential backoff between delivery attempts of an entry.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Maximum amount of deliverable entries that are tried to be claimed at once.
+const CLAIM_CANDIDATES: i64 = 10;
+/// Delivery attempts after which an entry is given up and marked as failed.
+const MAX_ATTEMPTS: u32 = 20;
+/// Interval in which delivered entries are checked for expiry.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Event waiting in the outbox for delivery to the Dapr sidecar.
+#[derive(Debug, Serialize, Deserialize, Clone, SimpleObject)]
+pub struct OutboxEntry {
+    /// Outbox entry UUID.
+    pub _id: Uuid,
+    /// Topic the event is published on.
+    pub topic: String,
+    /// Event data.
+    pub data: WishlistEventData,
+    /// Position of the event among the events of its wishlist, the version of the wishlist after the change.
+    #[serde(default)]
+    pub sequence: u64,
+    /// Timestamp when the entry was written.
+    pub created_at: DateTime,
+    /// Timestamp when the event was delivered, `None` while pending.
+    pub delivered_at: Option<DateTime>,
+    /// Timestamp when the delivery was given up after `MAX_ATTEMPTS` failed attempts.
+    ///
+    /// Failed entries are kept for inspection and no longer hold back later events of their wishlist.
+    #[serde(default)]
+    pub failed_at: Option<DateTime>,
+    /// Number of failed delivery attempts.
+    pub attempts: u32,
+    /// Timestamp before which no further delivery attempt is made.
+    pub next_attempt_at: DateTime,
+    /// Error of the last failed delivery attempt.
+    pub last_error: Option<String>,
+    /// Trace context of the mutation that wrote the entry, the published event joins this trace.
+    #[graphql(skip)]
+    #[serde(default)]
+    pub trace_context: HashMap<String, String>,
+}
+
+impl OutboxEntry {
+    /// Creates a pending entry of the event of a wishlist change, `None` if the change is no event.
+    ///
+    /// The entry is part of the current trace and due for delivery immediately.
+    ///
+    /// * `topic` - Topic the event is published on.
+    /// * `before` - Wishlist before the mutation, `None` if the wishlist was created.
+    /// * `after` - Wishlist after the mutation, `None` if the wishlist was deleted.
+    pub fn from_change(
+        topic: &str,
+        before: Option<&Wishlist>,
+        after: Option<&Wishlist>,
+    ) -> Option<Self> {
+        let data = WishlistEventData::from_change(before, after)?;
+        // Deleting a wishlist does not write a version, its deletion follows the last version.
+        let sequence = match (before, after) {
+            (_, Some(definitely_after)) => definitely_after.version,
+            (Some(definitely_before), None) => definitely_before.version + 1,
+            (None, None) => 0,
+        };
+        let current_timestamp = DateTime::now();
+        Some(Self {
+            _id: Uuid::new(),
+            topic: topic.to_string(),
+            data,
+            sequence,
+            created_at: current_timestamp,
+            delivered_at: None,
+            failed_at: None,
+            attempts: 0,
+            next_attempt_at: current_timestamp,
+            last_error: None,
+            trace_context: current_trace_carrier(),
+        })
+    }
+}
+
+/// Pending entries of the outbox.
+#[derive(SimpleObject)]
+pub struct OutboxBacklog {
+    /// The oldest pending entries.
+    pub nodes: Vec<OutboxEntry>,
+    /// The total amount of pending entries.
+    pub total_count: u64,
+}
+
+/// Handle to signal the drain task that new entries were written to the outbox.
+#[derive(Clone, Default)]
+pub struct OutboxSignal(Arc<Notify>);
+
+impl OutboxSignal {
+    /// Wakes up the drain task.
+    pub fn notify(&self) {
+        self.0.notify_one();
+    }
+}
+
+/// Starts a MongoDB session with an active transaction.
+///
+/// Changes of wishlists and the corresponding outbox entries are written in this transaction.
+pub async fn start_transaction(client: &Client) -> Result<ClientSession> {
+    let mut session = match client.start_session(None).await {
+        Ok(session) => session,
+        Err(_) => return Err(Error::new("Starting MongoDB session failed.")),
+    };
+    if session.start_transaction(None).await.is_err() {
+        return Err(Error::new("Starting MongoDB transaction failed."));
+    }
+    Ok(session)
+}
+
+/// Commits the transaction of a session.
+pub async fn commit_transaction(mut session: ClientSession) -> Result<()> {
+    match session.commit_transaction().await {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::new("Committing MongoDB transaction failed.")),
+    }
+}
+
+/// Writes the change of a wishlist to the outbox as part of the transaction of `session`.
+///
+/// * `collection` - MongoDB collection of the outbox.
+/// * `session` - Session with the transaction that also contains the wishlist change.
+/// * `topic` - Topic the event is published on.
+/// * `before` - Wishlist before the mutation, `None` if the wishlist was created.
+/// * `after` - Wishlist after the mutation, `None` if the wishlist was deleted.
+pub async fn enqueue_wishlist_change(
+    collection: &Collection<OutboxEntry>,
+    session: &mut ClientSession,
+    topic: &str,
+    before: Option<&Wishlist>,
+    after: Option<&Wishlist>,
+) -> Result<()> {
+    let entry = match OutboxEntry::from_change(topic, before, after) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    match collection
+        .insert_one_with_session(entry, None, session)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::new("Adding event to outbox failed in MongoDB.")),
+    }
+}
+
+/// Queries the oldest pending entries of the outbox.
+///
+/// * `collection` - MongoDB collection of outbox entries.
+/// * `first` - Maximum amount of entries to retrieve.
+pub async fn query_outbox_backlog(
+    collection: &Collection<OutboxEntry>,
+    first: Option<u32>,
+) -> Result<OutboxBacklog> {
+    let filter = pending_filter();
+    let find_options = FindOptions::builder()
+        .sort(doc! {"created_at": 1})
+        .limit(first.map(i64::from))
+        .build();
+    let total_count = match collection.count_documents(filter.clone(), None).await {
+        Ok(total_count) => total_count,
+        Err(_) => return Err(Error::new("Counting outbox entries failed in MongoDB.")),
+    };
+    match collection.find(filter, find_options).await {
+        Ok(cursor) => {
+            let nodes: Vec<OutboxEntry> = cursor.try_collect().await?;
+            Ok(OutboxBacklog { nodes, total_count })
+        }
+        Err(_) => Err(Error::new("Retrieving outbox entries failed in MongoDB.")),
+    }
+}
+
+/// Drains the outbox to the Dapr sidecar, runs until the service stops.
+///
+/// Waits for a signal of newly written entries or the poll interval, then delivers all due entries.
+/// Failed deliveries are retried with exponential backoff.
+pub async fn drain_outbox(
+    collection: Collection<OutboxEntry>,
+    event_publisher: EventPublisher,
+    signal: OutboxSignal,
+) {
+    info!("Outbox drain task started.");
+    loop {
+        while let Some(entry) = claim_next_entry(&collection).await {
+            deliver_entry(&collection, &event_publisher, entry).await;
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, signal.0.notified()).await;
+    }
+}
+
+/// Claims the oldest deliverable entry of the outbox by leasing it to this drain task.
+///
+/// The lease prevents drain tasks of other replicas from delivering the same entry concurrently.
+async fn claim_next_entry(collection: &Collection<OutboxEntry>) -> Option<OutboxEntry> {
+    let current_timestamp = DateTime::now();
+    let candidates: Vec<Document> = match collection
+        .aggregate(deliverable_entries_pipeline(current_timestamp), None)
+        .await
+    {
+        Ok(cursor) => match cursor.try_collect().await {
+            Ok(candidates) => candidates,
+            Err(error) => {
+                warn!(
+                    "Retrieving deliverable outbox entries failed in MongoDB: {}",
+                    error
+                );
+                return None;
+            }
+        },
+        Err(error) => {
+            warn!(
+                "Retrieving deliverable outbox entries failed in MongoDB: {}",
+                error
+            );
+            return None;
+        }
+    };
+    let lease_end = add_duration(current_timestamp, LEASE_DURATION);
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    for candidate in candidates {
+        let Some(id) = candidate.get("_id") else {
+            continue;
+        };
+        // Another drain task may have claimed or delivered the entry since it was read.
+        match collection
+            .find_one_and_update(
+                doc! {"_id": id, "delivered_at": null, "next_attempt_at": {"$lte": current_timestamp}},
+                doc! {"$set": {"next_attempt_at": lease_end}},
+                options.clone(),
+            )
+            .await
+        {
+            Ok(Some(entry)) => return Some(entry),
+            Ok(None) => (),
+            Err(error) => {
+                warn!("Claiming outbox entry failed in MongoDB: {}", error);
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Filter of the entries that are neither delivered nor failed.
+fn pending_filter() -> Document {
+    doc! {"delivered_at": null, "failed_at": null}
+}
+
+/// Aggregation pipeline of the oldest entries that can be delivered now.
+///
+/// Only the earliest pending entry of each wishlist, by `sequence`, can be delivered, which keeps the order of the
+/// events of a wishlist even if the delivery of an entry is retried with backoff or leased to another drain task.
+/// Wishlists whose earliest entry is not due are skipped, so they do not hold back other wishlists.
+fn deliverable_entries_pipeline(current_timestamp: DateTime) -> Vec<Document> {
+    vec![
+        doc! {"$match": pending_filter()},
+        doc! {"$sort": {"data.id": 1, "sequence": 1, "created_at": 1}},
+        doc! {"$group": {"_id": "$data.id", "entry": {"$first": "$$ROOT"}}},
+        doc! {"$replaceRoot": {"newRoot": "$entry"}},
+        doc! {"$match": {"next_attempt_at": {"$lte": current_timestamp}}},
+        doc! {"$sort": {"created_at": 1}},
+        doc! {"$limit": CLAIM_CANDIDATES},
+        doc! {"$project": {"_id": 1}},
+    ]
+}
+
+/// Deletes delivered entries that were delivered longer than `retention` ago, runs until the service stops.
+pub async fn purge_delivered_entries(collection: Collection<OutboxEntry>, retention: Duration) {
+    info!(
+        "Outbox purge task started with a retention period of {} hours.",
+        retention.as_secs() / 3600
+    );
+    loop {
+        let expiry = DateTime::from_millis(
+            DateTime::now().timestamp_millis() - retention.as_millis() as i64,
+        );
+        match collection
+            .delete_many(doc! {"delivered_at": {"$lt": expiry}}, None)
+            .await
+        {
+            Ok(result) if result.deleted_count > 0 => {
+                info!("Purged {} delivered outbox entries.", result.deleted_count)
+            }
+            Ok(_) => (),
+            Err(error) => warn!(
+                "Purging delivered outbox entries failed in MongoDB: {}",
+                error
+            ),
+        }
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
+
+/// Publishes a claimed entry and records the outcome of the delivery attempt.
+///
+/// The delivery is traced as a child of the trace context stored with the entry.
+async fn deliver_entry(
+    collection: &Collection<OutboxEntry>,
+    event_publisher: &EventPublisher,
+    entry: OutboxEntry,
+) {
+    let trace_context = start_span(
+        "publish_outbox_entry",
+        SpanKind::Producer,
+        vec![
+            KeyValue::new("messaging.system", "dapr"),
+            KeyValue::new("messaging.destination.name", entry.topic.clone()),
+            KeyValue::new("messaging.message.id", entry._id.to_string()),
+        ],
+        &context_from_carrier(&entry.trace_context),
+    );
+    let result = event_publisher
+        .publish(&entry.topic, &entry.data)
+        .with_context(trace_context.clone())
+        .await;
+    if let Err(error) = &result {
+        trace_context
+            .span()
+            .set_status(Status::error(error.message.clone()));
+    }
+    trace_context.span().end();
+    let update = match result {
+        Ok(()) => doc! {"$set": {"delivered_at": DateTime::now()}},
+        Err(error) if entry.attempts + 1 >= MAX_ATTEMPTS => {
+            error!(
+                "Delivering outbox entry of id: `{}` failed after {} attempts, giving up: {}",
+                entry._id, MAX_ATTEMPTS, error.message
+            );
+            doc! {"$set": {"attempts": MAX_ATTEMPTS, "failed_at": DateTime::now(), "last_error": error.message}}
+        }
+        Err(error) => {
+            let attempts = entry.attempts + 1;
+            warn!(
+                "Delivering outbox entry of id: `{}` failed after {} attempts: {}",
+                entry._id, attempts, error.message
+            );
+            let next_attempt_at = add_duration(DateTime::now(), backoff(attempts));
+            doc! {"$set": {"attempts": attempts, "next_attempt_at": next_attempt_at, "last_error": error.message}}
+        }
+    };
+    if let Err(error) = collection
+        .update_one(doc! {"_id": entry._id}, update, None)
+        .await
+    {
+        warn!(
+            "Recording delivery of outbox entry of id: `{}` failed in MongoDB: {}",
+            entry._id, error
+        );
+    }
+}
+
+/// Exponential backoff for the given amount of failed attempts, capped at `MAX_BACKOFF`.
+fn backoff(attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.min(16));
+    Duration::from_secs(1)
+        .saturating_mul(factor)
+        .min(MAX_BACKOFF)
+}
+
+/// Adds a duration to a MongoDB timestamp.
+fn add_duration(timestamp: DateTime, duration: Duration) -> DateTime {
+    DateTime::from_millis(timestamp.timestamp_millis() + duration.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::User;
+
+    /// Wishlist of a version.
+    fn wishlist(version: u64) -> Wishlist {
+        Wishlist {
+            _id: Uuid::new(),
+            user: User { _id: Uuid::new() },
+            name: "Birthday".to_string(),
+            created_at: DateTime::now(),
+            last_updated_at: DateTime::now(),
+            internal_product_variants: Vec::new(),
+            visibility: Default::default(),
+            share_token: None,
+            members: Vec::new(),
+            deleted_at: None,
+            version,
+        }
+    }
+
+    #[test]
+    fn sequences_entries_by_version_of_wishlist() {
+        let before = wishlist(3);
+        let after = Wishlist {
+            version: 4,
+            name: "Wedding".to_string(),
+            ..before.clone()
+        };
+        let topic = "wishlist/wishlist/updated";
+
+        let created = OutboxEntry::from_change(topic, None, Some(&before)).unwrap();
+        let updated = OutboxEntry::from_change(topic, Some(&before), Some(&after)).unwrap();
+        let deleted = OutboxEntry::from_change(topic, Some(&after), None).unwrap();
+
+        assert_eq!(
+            [created.sequence, updated.sequence, deleted.sequence],
+            [3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn selects_earliest_pending_entry_of_each_wishlist_before_due_date() {
+        let current_timestamp = DateTime::now();
+
+        let pipeline = deliverable_entries_pipeline(current_timestamp);
+
+        assert_eq!(
+            pipeline[..5],
+            [
+                doc! {"$match": {"delivered_at": null, "failed_at": null}},
+                doc! {"$sort": {"data.id": 1, "sequence": 1, "created_at": 1}},
+                doc! {"$group": {"_id": "$data.id", "entry": {"$first": "$$ROOT"}}},
+                doc! {"$replaceRoot": {"newRoot": "$entry"}},
+                doc! {"$match": {"next_attempt_at": {"$lte": current_timestamp}}},
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_backoff_at_maximum() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(30), MAX_BACKOFF);
+    }
+}
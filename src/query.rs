@@ -1,8 +1,16 @@
-use crate::{authentication::authenticate_user, user::User, Wishlist};
-use async_graphql::{Context, Error, Object, Result};
+use crate::{
+    authentication::{
+        authenticate_permissive_user, authenticate_user, authorize_wishlist, WishlistPermission,
+    },
+    outbox::OutboxBacklog,
+    repository::Repositories,
+    settings::Settings,
+    user::User,
+    Wishlist,
+};
+use async_graphql::{Context, Object, Result};
 
 use bson::Uuid;
-use mongodb::{bson::doc, Collection, Database};
 
 /// Describes GraphQL wishlist queries.
 pub struct Query;
@@ -16,9 +24,8 @@ impl Query {
         ctx: &Context<'a>,
         #[graphql(desc = "UUID of user to retrieve.")] id: Uuid,
     ) -> Result<User> {
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<User> = db_client.collection::<User>("users");
-        query_user(&collection, id).await
+        let repositories = ctx.data::<Repositories>()?;
+        repositories.users.find(id).await
     }
 
     /// Retrieves wishlist of specific id.
@@ -27,10 +34,9 @@ impl Query {
         ctx: &Context<'a>,
         #[graphql(desc = "UUID of wishlist to retrieve.")] id: Uuid,
     ) -> Result<Wishlist> {
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        let wishlist = query_wishlist(&collection, id).await?;
-        authenticate_user(&ctx, wishlist.user._id)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Read)?;
         Ok(wishlist)
     }
 
@@ -41,50 +47,50 @@ impl Query {
         ctx: &Context<'a>,
         #[graphql(key, desc = "UUID of wishlist to retrieve.")] id: Uuid,
     ) -> Result<Wishlist> {
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        let wishlist = query_wishlist(&collection, id).await?;
-        authenticate_user(&ctx, wishlist.user._id)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Read)?;
         Ok(wishlist)
     }
-}
 
-/// Shared function to query a wishlist from a MongoDB collection of wishlists
-///
-/// * `connection` - MongoDB database connection.
-/// * `id` - UUID of wishlist.
-pub async fn query_wishlist(collection: &Collection<Wishlist>, id: Uuid) -> Result<Wishlist> {
-    match collection.find_one(doc! {"_id": id }, None).await {
-        Ok(maybe_wishlist) => match maybe_wishlist {
-            Some(wishlist) => Ok(wishlist),
-            None => {
-                let message = format!("Wishlist with UUID: `{}` not found.", id);
-                Err(Error::new(message))
-            }
-        },
-        Err(_) => {
-            let message = format!("Wishlist with UUID: `{}` not found.", id);
-            Err(Error::new(message))
-        }
+    /// Retrieves a link-shared or public wishlist by its share token.
+    ///
+    /// Does not require authentication, the token grants read access.
+    async fn shared_wishlist<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Share token of wishlist to retrieve.")] token: String,
+    ) -> Result<Wishlist> {
+        let repositories = ctx.data::<Repositories>()?;
+        repositories.wishlists.find_shared(&token).await
     }
-}
 
-/// Shared function to query a user from a MongoDB collection of users.
-///
-/// * `connection` - MongoDB database connection.
-/// * `id` - UUID of user.
-pub async fn query_user(collection: &Collection<User>, id: Uuid) -> Result<User> {
-    match collection.find_one(doc! {"_id": id }, None).await {
-        Ok(maybe_user) => match maybe_user {
-            Some(user) => Ok(user),
-            None => {
-                let message = format!("User with UUID: `{}` not found.", id);
-                Err(Error::new(message))
-            }
-        },
-        Err(_) => {
-            let message = format!("User with UUID: `{}` not found.", id);
-            Err(Error::new(message))
-        }
+    /// Retrieves the wishlists of a user in the trash, most recently trashed first.
+    ///
+    /// Trashed wishlists are purged after the retention period.
+    async fn trashed_wishlists<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of user owning the trashed wishlists.")] user_id: Uuid,
+    ) -> Result<Vec<Wishlist>> {
+        authenticate_user(ctx, user_id)?;
+        let repositories = ctx.data::<Repositories>()?;
+        repositories.wishlists.find_trashed_of_user(user_id).await
+    }
+
+    /// Retrieves the events in the outbox that are not yet delivered to the Dapr sidecar.
+    ///
+    /// Only available to users with a permissive role.
+    async fn outbox_backlog<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Describes that the `first` N pending events should be retrieved.")]
+        first: Option<u32>,
+    ) -> Result<OutboxBacklog> {
+        authenticate_permissive_user(ctx)?;
+        let repositories = ctx.data::<Repositories>()?;
+        let settings = ctx.data::<Settings>()?;
+        settings.limits.check_page_size("first", first)?;
+        repositories.wishlists.outbox_backlog(first).await
     }
 }
@@ -0,0 +1,249 @@
+use std::{collections::HashSet, sync::Arc};
+
+use async_graphql::{MaybeUndefined, Result};
+use bson::{DateTime, Uuid};
+
+use crate::{
+    base_connection::{BaseConnection, CursorArguments},
+    event_publisher::WishlistEventType,
+    foreign_types::ProductVariant,
+    http_event_service::FailedEvent,
+    order_datatypes::WishlistOrderInput,
+    outbox::OutboxBacklog,
+    user::User,
+    wishlist::{Wishlist, WishlistVisibility},
+    wishlist_item::{WishlistItem, WishlistItemPriority, WishlistItemReservation},
+    wishlist_member::WishlistMember,
+};
+
+/// Storage of wishlists and of the outbox of their domain events.
+///
+/// Modifications that are domain events are written to the outbox atomically with the modification.
+#[async_graphql::async_trait::async_trait]
+pub trait WishlistRepository: Send + Sync {
+    /// Retrieves a wishlist, trashed wishlists are not found.
+    async fn find(&self, id: Uuid) -> Result<Wishlist>;
+
+    /// Retrieves a trashed wishlist.
+    async fn find_trashed(&self, id: Uuid) -> Result<Wishlist>;
+
+    /// Retrieves a link-shared or public wishlist by its share token.
+    async fn find_shared(&self, share_token: &str) -> Result<Wishlist>;
+
+    /// Retrieves the trashed wishlists of a user, most recently trashed first.
+    async fn find_trashed_of_user(&self, user_id: Uuid) -> Result<Vec<Wishlist>>;
+
+    /// Retrieves a page of the wishlists of a user, trashed wishlists are not included.
+    async fn paginate_of_user(&self, query: WishlistPageQuery) -> Result<BaseConnection<Wishlist>>;
+
+    /// Stores a new wishlist and writes its creation event to the outbox.
+    async fn create(&self, wishlist: &Wishlist) -> Result<Wishlist>;
+
+    /// Applies a modification to a wishlist and writes its event to the outbox, if it is a domain event.
+    ///
+    /// Returns the wishlist after the modification, `None` if the wishlist does not meet the precondition of the modification,
+    /// e.g. because it was modified or deleted concurrently.
+    ///
+    /// * `before` - Wishlist as read before the modification, describes the change in the event.
+    /// * `modification` - Modification to apply.
+    async fn apply(
+        &self,
+        before: &Wishlist,
+        modification: WishlistModification,
+    ) -> Result<Option<Wishlist>>;
+
+    /// Removes the items of an archived or deleted product variant from all wishlists.
+    async fn remove_product_variant(&self, product_variant_id: Uuid) -> Result<()>;
+
+    /// Deletes all wishlists owned by a user and removes the memberships of the user from all wishlists.
+    async fn remove_user(&self, user_id: Uuid) -> Result<()>;
+
+    /// Retrieves the oldest events in the outbox that are not yet delivered.
+    ///
+    /// * `first` - Maximum amount of events to retrieve.
+    async fn outbox_backlog(&self, first: Option<u32>) -> Result<OutboxBacklog>;
+}
+
+/// Storage of the users known from events of the user service.
+#[async_graphql::async_trait::async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Retrieves a user.
+    async fn find(&self, id: Uuid) -> Result<User>;
+
+    /// Stores a user, storing a known user again is no error.
+    async fn insert(&self, id: Uuid) -> Result<()>;
+
+    /// Deletes a user.
+    async fn delete(&self, id: Uuid) -> Result<()>;
+}
+
+/// Storage of the product variants known from events of the catalog service.
+#[async_graphql::async_trait::async_trait]
+pub trait ProductVariantRepository: Send + Sync {
+    /// Retrieves the known product variants among `ids`.
+    async fn find_many(&self, ids: &HashSet<Uuid>) -> Result<Vec<ProductVariant>>;
+
+    /// Stores a product variant, storing a known product variant again is no error.
+    async fn insert(&self, id: Uuid) -> Result<()>;
+
+    /// Deletes a product variant.
+    async fn delete(&self, id: Uuid) -> Result<()>;
+}
+
+/// Storage of the records of received Dapr events.
+#[async_graphql::async_trait::async_trait]
+pub trait EventRecordRepository: Send + Sync {
+    /// Checks if an event of the CloudEvent id was already processed.
+    async fn is_processed(&self, event_id: &str) -> Result<bool>;
+
+    /// Records that an event was processed, recording it again is no error.
+    async fn mark_processed(&self, event_id: &str, topic: &str) -> Result<()>;
+
+    /// Records an event that could not be processed.
+    async fn record_failed(&self, failed_event: FailedEvent) -> Result<()>;
+}
+
+/// Repositories of the service, shared by the GraphQL schema and the Dapr event endpoints.
+#[derive(Clone)]
+pub struct Repositories {
+    pub wishlists: Arc<dyn WishlistRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub product_variants: Arc<dyn ProductVariantRepository>,
+    pub events: Arc<dyn EventRecordRepository>,
+}
+
+impl Repositories {
+    /// Uses a single storage for all repositories.
+    pub fn new<R>(repository: R) -> Self
+    where
+        R: WishlistRepository
+            + UserRepository
+            + ProductVariantRepository
+            + EventRecordRepository
+            + 'static,
+    {
+        let repository = Arc::new(repository);
+        Self {
+            wishlists: repository.clone(),
+            users: repository.clone(),
+            product_variants: repository.clone(),
+            events: repository,
+        }
+    }
+}
+
+/// Page of the wishlists of a user to retrieve.
+pub struct WishlistPageQuery {
+    /// UUID of the user owning the wishlists.
+    pub user_id: Uuid,
+    /// UUID of the reader if it is not the owner.
+    ///
+    /// Restricts the wishlists to public ones and the ones the reader is an accepted member of.
+    pub reader_id: Option<Uuid>,
+    /// Order of the wishlists, `_id` is the tiebreaker of any order.
+    pub order: WishlistOrderInput,
+    /// Amount of wishlists to skip at the beginning.
+    pub skip: Option<u64>,
+    /// Cursor pagination arguments.
+    pub cursor_arguments: CursorArguments,
+}
+
+/// Modification of a single wishlist.
+///
+/// All modifications except reservations increase the version of the wishlist and are domain events.
+#[derive(Debug, Clone)]
+pub enum WishlistModification {
+    /// Sets name, items and/or visibility, if the wishlist still has `expected_version`.
+    Update {
+        expected_version: u64,
+        name: Option<String>,
+        items: Option<Vec<WishlistItem>>,
+        visibility: Option<WishlistVisibility>,
+        timestamp: DateTime,
+    },
+    /// Appends the items of product variants that are not on the wishlist yet, present items keep their metadata.
+    AddItems {
+        items: Vec<WishlistItem>,
+        timestamp: DateTime,
+    },
+    /// Removes the items of product variants.
+    RemoveItems {
+        product_variant_ids: Vec<Uuid>,
+        timestamp: DateTime,
+    },
+    /// Updates the metadata of the item of a product variant, if it is on the wishlist.
+    UpdateItem {
+        product_variant_id: Uuid,
+        /// Note to set, `Null` removes the note.
+        note: MaybeUndefined<String>,
+        quantity: Option<u32>,
+        priority: Option<WishlistItemPriority>,
+        timestamp: DateTime,
+    },
+    /// Replaces the share token and changes the visibility, if set.
+    Share {
+        share_token: String,
+        visibility: Option<WishlistVisibility>,
+        timestamp: DateTime,
+    },
+    /// Removes the share token and changes the visibility, if set.
+    RevokeShare {
+        visibility: Option<WishlistVisibility>,
+        timestamp: DateTime,
+    },
+    /// Reserves the item of a product variant, if it is not reserved yet.
+    Reserve {
+        product_variant_id: Uuid,
+        reservation: WishlistItemReservation,
+    },
+    /// Releases the reservation of the item of a product variant, if it is reserved by the user.
+    Release {
+        product_variant_id: Uuid,
+        user_id: Uuid,
+    },
+    /// Adds a member, if the user is not a member yet.
+    InviteMember { member: WishlistMember },
+    /// Accepts the pending invitation of a user.
+    AcceptInvitation { user_id: Uuid, timestamp: DateTime },
+    /// Removes a member or a pending invitation.
+    RemoveMember { user_id: Uuid, timestamp: DateTime },
+    /// Moves the wishlist to the trash.
+    Trash { timestamp: DateTime },
+    /// Restores the wishlist from the trash.
+    Restore,
+}
+
+impl WishlistModification {
+    /// Type of the domain event of the modification, `None` for reservations.
+    ///
+    /// Reservations are no domain event of the wishlist, which avoids spoiling the surprise for the owner.
+    pub fn event_type(&self) -> Option<WishlistEventType> {
+        match self {
+            Self::Reserve { .. } | Self::Release { .. } => None,
+            Self::Trash { .. } => Some(WishlistEventType::Deleted),
+            // Consumers received a deletion event when the wishlist was trashed, so it is created again.
+            Self::Restore => Some(WishlistEventType::Created),
+            _ => Some(WishlistEventType::Updated),
+        }
+    }
+
+    /// Whether the modification applies to a trashed wishlist.
+    pub fn applies_to_trashed(&self) -> bool {
+        matches!(self, Self::Restore)
+    }
+}
+
+/// States of a wishlist that describe the event of a modification.
+///
+/// Returns the wishlist before and after the modification, `None` if it was created or deleted from the perspective of consumers.
+pub fn event_states<'a>(
+    event_type: WishlistEventType,
+    before: &'a Wishlist,
+    after: &'a Wishlist,
+) -> (Option<&'a Wishlist>, Option<&'a Wishlist>) {
+    match event_type {
+        WishlistEventType::Created => (None, Some(after)),
+        WishlistEventType::Updated => (Some(before), Some(after)),
+        WishlistEventType::Deleted => (Some(before), None),
+    }
+}
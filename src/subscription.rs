@@ -0,0 +1,77 @@
+use async_graphql::{futures_util::Stream, Context, Result, Subscription as SubscriptionObject};
+use bson::Uuid;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    authentication::{
+        authenticate_user, authorize_wishlist, authorize_wishlist_of_user, AuthorizedUserHeader,
+        WishlistPermission,
+    },
+    repository::Repositories,
+    wishlist::Wishlist,
+    wishlist_change_broker::{WishlistChange, WishlistChangeBroker},
+};
+
+/// Describes GraphQL wishlist subscriptions.
+pub struct Subscription;
+
+#[SubscriptionObject]
+impl Subscription {
+    /// Streams a wishlist of specific id each time it changes.
+    ///
+    /// The stream ends when the wishlist is deleted or the subscriber is no longer permitted to read it.
+    async fn wishlist_updated<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of wishlist to watch.")] id: Uuid,
+    ) -> Result<impl Stream<Item = Wishlist>> {
+        let repositories = ctx.data::<Repositories>()?;
+        let mut receiver = ctx.data::<WishlistChangeBroker>()?.subscribe();
+        let wishlist = repositories.wishlists.find(id).await?;
+        authorize_wishlist(ctx, &wishlist, WishlistPermission::Read)?;
+        let authorized_user_header = ctx.data_opt::<AuthorizedUserHeader>().cloned();
+        Ok(async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(change) if change.wishlist_id() != id => continue,
+                    Ok(WishlistChange::Upserted(wishlist)) => {
+                        // Visibility or membership may have changed since the subscription started.
+                        let is_permitted = authorize_wishlist_of_user(
+                            authorized_user_header.as_ref(),
+                            &wishlist,
+                            WishlistPermission::Read,
+                        )
+                        .is_ok();
+                        if !is_permitted {
+                            break;
+                        }
+                        yield wishlist
+                    }
+                    Ok(WishlistChange::Deleted(_)) | Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                }
+            }
+        })
+    }
+
+    /// Streams the wishlists of a user each time one of them is created or updated.
+    async fn wishlists_of_user<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "UUID of user owning the wishlists.")] user_id: Uuid,
+    ) -> Result<impl Stream<Item = Wishlist>> {
+        authenticate_user(ctx, user_id)?;
+        let mut receiver = ctx.data::<WishlistChangeBroker>()?.subscribe();
+        Ok(async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(WishlistChange::Upserted(wishlist)) if wishlist.user._id == user_id => {
+                        yield wishlist
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, future::Future};
+
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{
+        FutureExt, SpanKind, Status, TraceContextExt, TraceError, Tracer, TracerProvider as _,
+    },
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Sampler, TracerProvider},
+    Resource,
+};
+
+use crate::settings::TracingSettings;
+
+/// Name of the tracer that creates the spans of this service.
+pub const TRACER_NAME: &str = "wishlist";
+
+/// Sets up W3C trace context propagation and, if an OTLP endpoint is configured, the export of spans.
+///
+/// Returns the tracer for the GraphQL extension, `None` if spans are not exported.
+/// Without export, spans are not recorded, but incoming trace contexts are still propagated to published events.
+pub fn init_tracing(settings: &TracingSettings) -> Result<Option<trace::Tracer>, TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let provider = build_tracer_provider(endpoint, settings)?;
+            let tracer = provider.tracer(TRACER_NAME);
+            global::set_tracer_provider(provider);
+            Ok(Some(tracer))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Builds a tracer provider that exports spans in batches over OTLP/HTTP.
+///
+/// * `endpoint` - Base URL of the collector, spans are sent to `<endpoint>/v1/traces`.
+/// * `settings` - Service name and sample ratio.
+fn build_tracer_provider(
+    endpoint: &str,
+    settings: &TracingSettings,
+) -> Result<TracerProvider, TraceError> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+    let config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sample_ratio,
+        ))))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(config)
+        .build())
+}
+
+/// Reads the trace context of an HTTP request from its `traceparent` and `tracestate` headers.
+pub fn context_from_headers(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Reads a trace context from `traceparent` and `tracestate` entries, e.g. of a CloudEvent or an outbox entry.
+pub fn context_from_carrier(carrier: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+/// Writes the current trace context as `traceparent` and `tracestate` entries.
+///
+/// Empty if there is no current trace.
+pub fn current_trace_carrier() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Context::current(), &mut carrier)
+    });
+    carrier
+}
+
+/// Starts a span and returns `parent` with the span as its active span.
+///
+/// * `name` - Name of the span.
+/// * `kind` - Role of the span in the trace.
+/// * `attributes` - Attributes of the span.
+/// * `parent` - Context the span is a child of.
+pub fn start_span(
+    name: &'static str,
+    kind: SpanKind,
+    attributes: Vec<KeyValue>,
+    parent: &Context,
+) -> Context {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+/// Runs `future` in a child span of the current span, failures are recorded as the status of the span.
+///
+/// * `name` - Name of the span.
+/// * `attributes` - Attributes of the span.
+/// * `future` - Traced operation.
+pub async fn traced<T>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    future: impl Future<Output = async_graphql::Result<T>>,
+) -> async_graphql::Result<T> {
+    let context = start_span(name, SpanKind::Internal, attributes, &Context::current());
+    let result = future.with_context(context.clone()).await;
+    if let Err(error) = &result {
+        context
+            .span()
+            .set_status(Status::error(error.message.clone()));
+    }
+    context.span().end();
+    result
+}
+
+/// Reads propagation fields from HTTP headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use axum::{body::Bytes, extract::State, routing::post, Router, Server};
+    use opentelemetry::trace::{Span, TraceId};
+    use tokio::sync::Mutex;
+
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    /// Starts a stand-in of an OTLP/HTTP collector that records the bodies of received trace exports.
+    async fn start_collector_stand_in() -> (SocketAddr, Arc<Mutex<Vec<Bytes>>>) {
+        let exports = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route(
+                "/v1/traces",
+                post(
+                    |State(exports): State<Arc<Mutex<Vec<Bytes>>>>, body: Bytes| async move {
+                        exports.lock().await.push(body);
+                    },
+                ),
+            )
+            .with_state(exports.clone());
+        let server =
+            Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+        let address = server.local_addr();
+        tokio::spawn(server);
+        (address, exports)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_spans_to_collector() {
+        let (address, exports) = start_collector_stand_in().await;
+        let settings = TracingSettings::default();
+        let provider = build_tracer_provider(&format!("http://{}", address), &settings).unwrap();
+        let mut span = provider
+            .tracer(TRACER_NAME)
+            .start("exports_spans_to_collector");
+        span.end();
+        let flush_results = tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+        assert!(flush_results.iter().all(Result::is_ok));
+        let exports = exports.lock().await;
+        assert_eq!(exports.len(), 1);
+        let contains_span_name = exports[0]
+            .windows("exports_spans_to_collector".len())
+            .any(|window| window == b"exports_spans_to_collector");
+        assert!(contains_span_name);
+    }
+
+    #[test]
+    fn extracts_trace_context_of_headers_and_carriers() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", TRACEPARENT.parse().unwrap());
+        let header_context = context_from_headers(&headers);
+        assert_eq!(header_context.span().span_context().trace_id(), trace_id);
+        assert!(header_context.span().span_context().is_remote());
+        let carrier = HashMap::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+        let carrier_context = context_from_carrier(&carrier);
+        assert_eq!(carrier_context.span().span_context().trace_id(), trace_id);
+        let _guard = carrier_context.attach();
+        assert_eq!(
+            current_trace_carrier().get("traceparent").unwrap(),
+            TRACEPARENT
+        );
+    }
+}
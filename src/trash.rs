@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use bson::{doc, DateTime};
+use log::{info, warn};
+use mongodb::Collection;
+
+use crate::wishlist::Wishlist;
+
+/// Interval in which trashed wishlists are checked for expiry.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Hard-deletes wishlists that are in the trash for longer than `retention`, runs until the service stops.
+///
+/// Deletion events are published when a wishlist is moved to the trash, so purging publishes no events.
+pub async fn purge_trashed_wishlists(collection: Collection<Wishlist>, retention: Duration) {
+    info!(
+        "Trash purge task started with a retention period of {} days.",
+        retention.as_secs() / (24 * 3600)
+    );
+    loop {
+        let expiry = DateTime::from_millis(
+            DateTime::now().timestamp_millis() - retention.as_millis() as i64,
+        );
+        match collection
+            .delete_many(doc! {"deleted_at": {"$lt": expiry}}, None)
+            .await
+        {
+            Ok(result) if result.deleted_count > 0 => {
+                info!("Purged {} trashed wishlists.", result.deleted_count)
+            }
+            Ok(_) => (),
+            Err(error) => warn!("Purging trashed wishlists failed in MongoDB: {}", error),
+        }
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
@@ -1,14 +1,13 @@
-use async_graphql::{ComplexObject, Context, Error, Result, SimpleObject};
-use bson::{doc, Document, Uuid};
-use mongodb::{options::FindOptions, Collection, Database};
-use mongodb_cursor_pagination::{error::CursorError, FindResult, PaginatedCursor};
+use async_graphql::{ComplexObject, Context, Result, SimpleObject};
+use bson::Uuid;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    authentication::authenticate_user,
-    base_connection::{BaseConnection, FindResultWrapper},
+    authentication::{authenticate_user, authenticated_user_id},
+    base_connection::CursorArguments,
     order_datatypes::WishlistOrderInput,
-    wishlist::Wishlist,
+    repository::{Repositories, WishlistPageQuery},
+    settings::Settings,
     wishlist_connection::WishlistConnection,
 };
 
@@ -23,40 +22,48 @@ pub struct User {
 #[ComplexObject]
 impl User {
     /// Retrieves wishlists of user.
+    ///
+    /// Supports cursor based pagination with `first`/`after` and `last`/`before`, as well as skip based pagination.
+    /// Users other than the owner only retrieve the public wishlists and the ones they are an accepted member of.
+    #[allow(clippy::too_many_arguments)]
     async fn wishlists<'a>(
         &self,
         ctx: &Context<'a>,
-        #[graphql(desc = "Describes that the `first` N wishlists should be retrieved.")]
+        #[graphql(
+            desc = "Describes that the `first` N wishlists should be retrieved, 25 if neither `first` nor `last` is set."
+        )]
         first: Option<u32>,
         #[graphql(desc = "Describes how many wishlists should be skipped at the beginning.")]
         skip: Option<u64>,
+        #[graphql(desc = "Describes that wishlists after this cursor should be retrieved.")]
+        after: Option<String>,
+        #[graphql(desc = "Describes that the `last` N wishlists should be retrieved.")]
+        last: Option<u32>,
+        #[graphql(desc = "Describes that wishlists before this cursor should be retrieved.")]
+        before: Option<String>,
         #[graphql(desc = "Specifies the order in which wishlists are retrieved.")] order_by: Option<
             WishlistOrderInput,
         >,
     ) -> Result<WishlistConnection> {
-        authenticate_user(&ctx, self._id)?;
-        let db_client = ctx.data::<Database>()?;
-        let collection: Collection<Wishlist> = db_client.collection::<Wishlist>("wishlists");
-        let wishlist_order = order_by.unwrap_or_default();
-        let sorting_doc = doc! {wishlist_order.field.unwrap_or_default().as_str(): i32::from(wishlist_order.direction.unwrap_or_default())};
-        let find_options = FindOptions::builder()
-            .skip(skip)
-            .limit(first.map(|v| i64::from(v)))
-            .sort(sorting_doc)
-            .build();
-        let document_collection = collection.clone_with_type::<Document>();
-        let filter = doc! {"user._id": self._id};
-        let maybe_find_results: Result<FindResult<Wishlist>, CursorError> =
-            PaginatedCursor::new(Some(find_options.clone()), None, None)
-                .find(&document_collection, Some(&filter))
-                .await;
-        match maybe_find_results {
-            Ok(find_results) => {
-                let find_result_wrapper = FindResultWrapper(find_results);
-                let connection = Into::<BaseConnection<Wishlist>>::into(find_result_wrapper);
-                Ok(Into::<WishlistConnection>::into(connection))
-            }
-            Err(_) => return Err(Error::new("Retrieving wishlists failed in MongoDB.")),
-        }
+        let reader_id = authenticated_user_id(ctx)?;
+        let is_owner_authorized = authenticate_user(ctx, self._id).is_ok();
+        let repositories = ctx.data::<Repositories>()?;
+        let settings = ctx.data::<Settings>()?;
+        settings.limits.check_page_size("first", first)?;
+        settings.limits.check_page_size("last", last)?;
+        let query = WishlistPageQuery {
+            user_id: self._id,
+            reader_id: (!is_owner_authorized).then_some(reader_id),
+            order: order_by.unwrap_or_default(),
+            skip,
+            cursor_arguments: CursorArguments {
+                first,
+                after,
+                last,
+                before,
+            },
+        };
+        let connection = repositories.wishlists.paginate_of_user(query).await?;
+        Ok(connection.into())
     }
 }
@@ -1,17 +1,19 @@
-use std::{cmp::Ordering, collections::HashSet};
+use std::cmp::Ordering;
 
-use async_graphql::{
-    ComplexObject, Result, SimpleObject,
-};
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use bson::datetime::DateTime;
-use bson::Uuid;
+use bson::{doc, Uuid};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    authentication::{authenticated_user_id, authorize_wishlist, WishlistPermission},
     foreign_types::ProductVariant,
-    order_datatypes::{CommonOrderInput, OrderDirection},
+    order_datatypes::{OrderDirection, WishlistItemOrderField, WishlistItemOrderInput},
     product_variant_connection::ProductVariantConnection,
     user::User,
+    wishlist_item::WishlistItem,
+    wishlist_item_connection::WishlistItemConnection,
+    wishlist_member::{WishlistMember, WishlistMemberStatus},
 };
 
 /// The Wishlist of a user.
@@ -28,12 +30,73 @@ pub struct Wishlist {
     pub created_at: DateTime,
     /// Timestamp when Wishlist was last updated.
     pub last_updated_at: DateTime,
+    /// Items of the wishlist, stored under the name of the former product variant references.
+    #[graphql(skip)]
+    pub internal_product_variants: Vec<WishlistItem>,
+    /// Visibility of Wishlist for users other than the owner.
+    #[serde(default)]
+    pub visibility: WishlistVisibility,
+    /// Token that grants read access to a link-shared Wishlist, `None` if not shared or revoked.
+    #[graphql(skip)]
+    #[serde(default)]
+    pub share_token: Option<String>,
+    /// Members of Wishlist, which collaborate with the owner.
     #[graphql(skip)]
-    pub internal_product_variants: HashSet<ProductVariant>,
+    #[serde(default)]
+    pub members: Vec<WishlistMember>,
+    /// Timestamp when Wishlist was moved to the trash, `None` if not trashed.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime>,
+    /// Version of Wishlist, increases with each change. Used for optimistic concurrency control.
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// Visibility of a wishlist for users other than the owner.
+#[derive(Enum, Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WishlistVisibility {
+    /// Only the owner and users with a permissive role can read the wishlist.
+    #[default]
+    Private,
+    /// Additionally, anyone with the share token can read the wishlist.
+    LinkShared,
+    /// Anyone can read the wishlist.
+    Public,
+}
+
+impl WishlistVisibility {
+    /// Representation of the visibility in MongoDB documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WishlistVisibility::Private => "private",
+            WishlistVisibility::LinkShared => "link_shared",
+            WishlistVisibility::Public => "public",
+        }
+    }
 }
 
 #[ComplexObject]
 impl Wishlist {
+    /// Token that grants read access to the link-shared Wishlist.
+    ///
+    /// Only visible to the owner and users with a permissive role.
+    async fn share_token<'a>(&self, ctx: &Context<'a>) -> Option<String> {
+        authorize_wishlist(ctx, self, WishlistPermission::Owner)
+            .ok()
+            .and(self.share_token.clone())
+    }
+
+    /// Members of the Wishlist, which collaborate with the owner.
+    ///
+    /// Only visible to editors, the owner and users with a permissive role.
+    async fn members<'a>(&self, ctx: &Context<'a>) -> Vec<WishlistMember> {
+        match authorize_wishlist(ctx, self, WishlistPermission::Write) {
+            Ok(()) => self.members.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+
     /// Retrieves product variants.
     async fn product_variants(
         &self,
@@ -44,45 +107,118 @@ impl Wishlist {
         )]
         skip: Option<usize>,
         #[graphql(desc = "Specifies the order in which product variants are retrieved.")] order_by: Option<
-            CommonOrderInput,
+            WishlistItemOrderInput,
         >,
     ) -> Result<ProductVariantConnection> {
-        let mut product_variants: Vec<ProductVariant> =
-            self.internal_product_variants.clone().into_iter().collect();
-        sort_product_variants(&mut product_variants, order_by);
-        let total_count = product_variants.len();
+        let (items_part, has_next_page, total_count) = self.paginate_items(first, skip, order_by);
+        Ok(ProductVariantConnection {
+            nodes: items_part
+                .into_iter()
+                .map(|item| ProductVariant { _id: item._id })
+                .collect(),
+            has_next_page,
+            total_count,
+        })
+    }
+
+    /// Retrieves items, which are product variants together with the metadata of the wish.
+    ///
+    /// Reservations are hidden from the owner, unless `revealReservations` is set.
+    async fn items<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Describes that the `first` N items should be retrieved.")] first: Option<
+            usize,
+        >,
+        #[graphql(desc = "Describes how many items should be skipped at the beginning.")]
+        skip: Option<usize>,
+        #[graphql(desc = "Specifies the order in which items are retrieved.")] order_by: Option<
+            WishlistItemOrderInput,
+        >,
+        #[graphql(desc = "Reveals reservations to the owner, which spoils the surprise.")]
+        reveal_reservations: Option<bool>,
+    ) -> Result<WishlistItemConnection> {
+        let (mut items_part, has_next_page, total_count) =
+            self.paginate_items(first, skip, order_by);
+        let is_owner = authenticated_user_id(ctx).is_ok_and(|id| id == self.user._id);
+        if is_owner && !reveal_reservations.unwrap_or(false) {
+            items_part
+                .iter_mut()
+                .for_each(|item| item.reservation = None);
+        }
+        Ok(WishlistItemConnection {
+            nodes: items_part,
+            has_next_page,
+            total_count,
+        })
+    }
+}
+
+impl Wishlist {
+    /// Sorts and paginates the items of the wishlist.
+    ///
+    /// Returns the items of the page, whether there is a next page and the total amount of items.
+    fn paginate_items(
+        &self,
+        first: Option<usize>,
+        skip: Option<usize>,
+        order_by: Option<WishlistItemOrderInput>,
+    ) -> (Vec<WishlistItem>, bool, u64) {
+        let mut items = self.internal_product_variants.clone();
+        sort_items(&mut items, order_by);
+        let total_count = items.len();
         let definitely_skip = skip.unwrap_or(0);
         let definitely_first = first.unwrap_or(usize::MAX);
-        let product_variants_part: Vec<ProductVariant> = product_variants
+        let items_part: Vec<WishlistItem> = items
             .into_iter()
             .skip(definitely_skip)
             .take(definitely_first)
             .collect();
-        let has_next_page = total_count > product_variants_part.len() + definitely_skip;
-        Ok(ProductVariantConnection {
-            nodes: product_variants_part,
-            has_next_page,
-            total_count: total_count as u64,
+        let has_next_page = total_count > items_part.len() + definitely_skip;
+        (items_part, has_next_page, total_count as u64)
+    }
+
+    /// Retrieves the member of a user, if the user accepted the invitation.
+    pub fn accepted_member(&self, user_id: Uuid) -> Option<&WishlistMember> {
+        self.members.iter().find(|member| {
+            member.user._id == user_id && member.status == WishlistMemberStatus::Accepted
         })
     }
+
+    /// Checks if a user is a member of the wishlist, regardless of the invitation status.
+    pub fn is_member(&self, user_id: Uuid) -> bool {
+        self.members.iter().any(|member| member.user._id == user_id)
+    }
+
+    /// Checks if a product variant is on the wishlist.
+    pub fn contains_product_variant(&self, id: Uuid) -> bool {
+        self.internal_product_variants
+            .iter()
+            .any(|item| item._id == id)
+    }
 }
 
-/// Sorts vector of product variants according to BaseOrder.
+/// Sorts vector of wishlist items according to WishlistItemOrderInput.
 ///
-/// * `product_variants` - Vector of product variants to sort.
+/// Items with equal values in the ordered field are ordered by the product variant UUID.
+///
+/// * `items` - Vector of wishlist items to sort.
 /// * `order_by` - Specifies order of sorted result.
-fn sort_product_variants(
-    product_variants: &mut Vec<ProductVariant>,
-    order_by: Option<CommonOrderInput>,
-) {
-    let comparator: fn(&ProductVariant, &ProductVariant) -> bool =
-        match order_by.unwrap_or_default().direction.unwrap_or_default() {
-            OrderDirection::Asc => |x, y| x < y,
-            OrderDirection::Desc => |x, y| x > y,
-        };
-    product_variants.sort_by(|x, y| match comparator(x, y) {
-        true => Ordering::Less,
-        false => Ordering::Greater,
+fn sort_items(items: &mut [WishlistItem], order_by: Option<WishlistItemOrderInput>) {
+    let order = order_by.unwrap_or_default();
+    let field = order.field.unwrap_or_default();
+    items.sort_by(|x, y| {
+        let ordering = match field {
+            WishlistItemOrderField::Id => Ordering::Equal,
+            WishlistItemOrderField::AddedAt => x.added_at.cmp(&y.added_at),
+            WishlistItemOrderField::Quantity => x.quantity.cmp(&y.quantity),
+            WishlistItemOrderField::Priority => x.priority.cmp(&y.priority),
+        }
+        .then_with(|| x._id.bytes().cmp(&y._id.bytes()));
+        match order.direction.unwrap_or_default() {
+            OrderDirection::Asc => ordering,
+            OrderDirection::Desc => ordering.reverse(),
+        }
     });
 }
 
@@ -90,4 +226,4 @@ impl From<Wishlist> for Uuid {
     fn from(value: Wishlist) -> Self {
         value._id
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,166 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bson::{Document, Uuid};
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::{
+    change_stream::event::{OperationType, ResumeToken},
+    options::{ChangeStreamOptions, FullDocumentType},
+    Collection,
+};
+use tokio::sync::broadcast;
+
+use crate::wishlist::Wishlist;
+
+/// Amount of changes buffered for slow subscribers before they start to miss changes.
+const CHANNEL_CAPACITY: usize = 1024;
+/// Delay before watching the change stream again after it failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Change of a wishlist that is broadcast to GraphQL subscriptions.
+#[derive(Debug, Clone)]
+pub enum WishlistChange {
+    /// Wishlist was created or updated, contains the wishlist after the change.
+    Upserted(Wishlist),
+    /// Wishlist of UUID was deleted.
+    Deleted(Uuid),
+}
+
+/// Trashed wishlists are deleted from the perspective of subscriptions.
+impl From<Wishlist> for WishlistChange {
+    fn from(value: Wishlist) -> Self {
+        match value.deleted_at {
+            Some(_) => Self::Deleted(value._id),
+            None => Self::Upserted(value),
+        }
+    }
+}
+
+impl WishlistChange {
+    /// UUID of the changed wishlist.
+    pub fn wishlist_id(&self) -> Uuid {
+        match self {
+            Self::Upserted(wishlist) => wishlist._id,
+            Self::Deleted(id) => *id,
+        }
+    }
+}
+
+/// Broadcasts wishlist changes to GraphQL subscriptions.
+///
+/// Changes are fed from the MongoDB change stream, which also contains changes made by other replicas.
+/// While the change stream is not available, e.g. if MongoDB does not run as a replica set,
+/// changes are fed from the mutation results of this replica.
+#[derive(Clone)]
+pub struct WishlistChangeBroker {
+    sender: broadcast::Sender<WishlistChange>,
+    change_stream_active: Arc<AtomicBool>,
+}
+
+impl Default for WishlistChangeBroker {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            change_stream_active: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl WishlistChangeBroker {
+    /// Subscribes to all subsequent wishlist changes.
+    pub fn subscribe(&self) -> broadcast::Receiver<WishlistChange> {
+        self.sender.subscribe()
+    }
+
+    /// Broadcasts the result of a mutation of this replica.
+    ///
+    /// Skipped while the change stream is active, as the change stream contains the change as well.
+    ///
+    /// * `id` - UUID of the changed wishlist.
+    /// * `after` - Wishlist after the mutation, `None` if the wishlist was deleted.
+    pub fn publish_mutation_result(&self, id: Uuid, after: Option<&Wishlist>) {
+        if self.change_stream_active.load(Ordering::Relaxed) {
+            return;
+        }
+        let change = match after {
+            Some(wishlist) => WishlistChange::from(wishlist.clone()),
+            None => WishlistChange::Deleted(id),
+        };
+        self.publish(change);
+    }
+
+    /// Broadcasts a change, having no subscribers is not an error.
+    fn publish(&self, change: WishlistChange) {
+        let _ = self.sender.send(change);
+    }
+
+    /// Feeds the broker from the MongoDB change stream of wishlists, runs until the service stops.
+    ///
+    /// Resumes after the last received change if the change stream fails.
+    pub async fn watch_change_stream(self, collection: Collection<Wishlist>) {
+        let mut resume_token: Option<ResumeToken> = None;
+        loop {
+            let options = ChangeStreamOptions::builder()
+                .full_document(Some(FullDocumentType::UpdateLookup))
+                .resume_after(resume_token.clone())
+                .build();
+            match collection.watch(None, options).await {
+                Ok(mut change_stream) => {
+                    info!("Watching MongoDB change stream of wishlists.");
+                    self.change_stream_active.store(true, Ordering::Relaxed);
+                    loop {
+                        match change_stream.try_next().await {
+                            Ok(Some(event)) => {
+                                resume_token = Some(event.id.clone());
+                                let maybe_change = match event.operation_type {
+                                    OperationType::Insert
+                                    | OperationType::Update
+                                    | OperationType::Replace => {
+                                        event.full_document.map(WishlistChange::from)
+                                    }
+                                    OperationType::Delete => event
+                                        .document_key
+                                        .as_ref()
+                                        .and_then(document_key_id)
+                                        .map(WishlistChange::Deleted),
+                                    _ => None,
+                                };
+                                if let Some(change) = maybe_change {
+                                    self.publish(change);
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(error) => {
+                                warn!("MongoDB change stream of wishlists failed: {}", error);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(error) => {
+                    warn!(
+                        "Watching MongoDB change stream of wishlists is not possible, subscriptions only receive changes of this replica: {}",
+                        error
+                    );
+                }
+            }
+            self.change_stream_active.store(false, Ordering::Relaxed);
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+/// Extracts the wishlist UUID from the document key of a change stream event.
+fn document_key_id(document_key: &Document) -> Option<Uuid> {
+    match document_key.get("_id") {
+        Some(bson::Bson::Binary(binary)) => binary.to_uuid().ok(),
+        _ => None,
+    }
+}
@@ -1,6 +1,9 @@
 use async_graphql::SimpleObject;
 
-use crate::{base_connection::BaseConnection, wishlist::Wishlist};
+use crate::{
+    base_connection::{BaseConnection, PageInfo},
+    wishlist::Wishlist,
+};
 
 /// A connection of Wishlists.
 #[derive(SimpleObject)]
@@ -12,6 +15,8 @@ pub struct WishlistConnection {
     pub has_next_page: bool,
     /// The total amount of items in this connection.
     pub total_count: u64,
+    /// Cursor based pagination information of this connection.
+    pub page_info: PageInfo,
 }
 
 /// Implementation of conversion from BaseConnection<Wishlist> to WishlistConnection.
@@ -23,6 +28,7 @@ impl From<BaseConnection<Wishlist>> for WishlistConnection {
             nodes: value.nodes,
             has_next_page: value.has_next_page,
             total_count: value.total_count,
+            page_info: value.page_info,
         }
     }
 }
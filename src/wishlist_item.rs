@@ -0,0 +1,109 @@
+use async_graphql::{ComplexObject, Enum, SimpleObject};
+use bson::{datetime::DateTime, doc, Bson, Uuid};
+use serde::{Deserialize, Serialize};
+
+use crate::{foreign_types::ProductVariant, user::User};
+
+/// Product variant on a wishlist together with metadata describing the wish.
+///
+/// Stored in the `internal_product_variants` field of a wishlist document.
+/// The product variant UUID is stored as `_id`, which keeps item documents compatible with plain product variant references.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, SimpleObject)]
+#[graphql(complex)]
+pub struct WishlistItem {
+    /// UUID of the product variant.
+    #[graphql(skip)]
+    pub _id: Uuid,
+    /// Timestamp when the product variant was added to the wishlist.
+    pub added_at: DateTime,
+    /// Optional note of the user, e.g. a preferred color.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Desired quantity of the product variant.
+    #[serde(default = "default_quantity")]
+    pub quantity: u32,
+    /// Priority of the wish.
+    #[serde(default)]
+    pub priority: WishlistItemPriority,
+    /// Reservation of a gift-giver, hidden from the owner by default.
+    #[serde(default)]
+    pub reservation: Option<WishlistItemReservation>,
+}
+
+/// Reservation of a wishlist item by a gift-giver, which prevents duplicate gifts.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, SimpleObject)]
+pub struct WishlistItemReservation {
+    /// User that reserved the item.
+    pub user: User,
+    /// Timestamp when the item was reserved.
+    pub reserved_at: DateTime,
+}
+
+#[ComplexObject]
+impl WishlistItem {
+    /// Product variant of the item.
+    async fn product_variant(&self) -> ProductVariant {
+        ProductVariant { _id: self._id }
+    }
+}
+
+impl WishlistItem {
+    /// Creates an item with default metadata for a product variant added at `added_at`.
+    pub fn new(product_variant_id: Uuid, added_at: DateTime) -> Self {
+        Self {
+            _id: product_variant_id,
+            added_at,
+            note: None,
+            quantity: default_quantity(),
+            priority: WishlistItemPriority::default(),
+            reservation: None,
+        }
+    }
+}
+
+impl From<WishlistItem> for Bson {
+    fn from(value: WishlistItem) -> Self {
+        Bson::Document(doc! {
+            "_id": value._id,
+            "added_at": value.added_at,
+            "note": value.note,
+            "quantity": value.quantity,
+            "priority": value.priority.as_str(),
+            "reservation": value.reservation.map(|reservation| doc! {
+                "user": {"_id": reservation.user._id},
+                "reserved_at": reservation.reserved_at,
+            }),
+        })
+    }
+}
+
+/// Default quantity of an item.
+pub fn default_quantity() -> u32 {
+    1
+}
+
+/// Priority of a wishlist item.
+#[derive(
+    Enum, Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WishlistItemPriority {
+    /// Nice to have.
+    Low,
+    /// Regular wish.
+    #[default]
+    Medium,
+    /// Most wanted.
+    High,
+}
+
+impl WishlistItemPriority {
+    /// Representation of the priority in MongoDB documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WishlistItemPriority::Low => "low",
+            WishlistItemPriority::Medium => "medium",
+            WishlistItemPriority::High => "high",
+        }
+    }
+}
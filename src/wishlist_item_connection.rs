@@ -0,0 +1,28 @@
+use async_graphql::SimpleObject;
+
+use crate::{base_connection::BaseConnection, wishlist_item::WishlistItem};
+
+/// A connection of WishlistItems.
+#[derive(SimpleObject)]
+#[graphql(shareable)]
+pub struct WishlistItemConnection {
+    /// The resulting entities.
+    pub nodes: Vec<WishlistItem>,
+    /// Whether this connection has a next page.
+    pub has_next_page: bool,
+    /// The total amount of items in this connection.
+    pub total_count: u64,
+}
+
+/// Implementation of conversion from BaseConnection<WishlistItem> to WishlistItemConnection.
+///
+/// Prevents GraphQL naming conflicts.
+impl From<BaseConnection<WishlistItem>> for WishlistItemConnection {
+    fn from(value: BaseConnection<WishlistItem>) -> Self {
+        Self {
+            nodes: value.nodes,
+            has_next_page: value.has_next_page,
+            total_count: value.total_count,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use async_graphql::{Enum, SimpleObject};
+use bson::{datetime::DateTime, doc, Bson};
+use serde::{Deserialize, Serialize};
+
+use crate::{authentication::WishlistPermission, user::User};
+
+/// Member of a collaborative wishlist, who is not the owner.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, SimpleObject)]
+pub struct WishlistMember {
+    /// User that is a member.
+    pub user: User,
+    /// Role of the member, defines the permitted operations.
+    pub role: WishlistMemberRole,
+    /// Whether the member accepted the invitation.
+    pub status: WishlistMemberStatus,
+    /// Timestamp when the member was invited.
+    pub invited_at: DateTime,
+    /// Timestamp when the member accepted the invitation.
+    pub accepted_at: Option<DateTime>,
+}
+
+impl From<WishlistMember> for Bson {
+    fn from(value: WishlistMember) -> Self {
+        Bson::Document(doc! {
+            "user": {"_id": value.user._id},
+            "role": value.role.as_str(),
+            "status": value.status.as_str(),
+            "invited_at": value.invited_at,
+            "accepted_at": value.accepted_at,
+        })
+    }
+}
+
+/// Role of a wishlist member.
+#[derive(Enum, Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WishlistMemberRole {
+    /// Can read the wishlist.
+    Viewer,
+    /// Can read the wishlist and modify its name and items.
+    Editor,
+}
+
+impl WishlistMemberRole {
+    /// Representation of the role in MongoDB documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WishlistMemberRole::Viewer => "viewer",
+            WishlistMemberRole::Editor => "editor",
+        }
+    }
+
+    /// Defines if the role permits an operation, owner operations are never permitted to members.
+    pub fn permits(self, permission: WishlistPermission) -> bool {
+        match permission {
+            WishlistPermission::Read => true,
+            WishlistPermission::Write => self == Self::Editor,
+            WishlistPermission::Owner => false,
+        }
+    }
+}
+
+/// Status of the membership invitation.
+#[derive(Enum, Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WishlistMemberStatus {
+    /// Member was invited, but did not accept yet. Grants no permissions.
+    Invited,
+    /// Member accepted the invitation.
+    Accepted,
+}
+
+impl WishlistMemberStatus {
+    /// Representation of the status in MongoDB documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WishlistMemberStatus::Invited => "invited",
+            WishlistMemberStatus::Accepted => "accepted",
+        }
+    }
+}